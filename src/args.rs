@@ -78,6 +78,19 @@ impl<'a> Iterator for Preprocessor<'a> {
 	}
 }
 
+impl<'a> Preprocessor<'a> {
+	/// Take the next argument only if it satisfies `f`, e.g. for a flag with an optional value.
+	pub fn next_if(&mut self, f: impl FnOnce(&str) -> bool) -> Option<Cow<'a, str>> {
+		let s = self.next()?;
+		if f(&s) {
+			Some(s)
+		} else {
+			self.buf.push_front(s);
+			None
+		}
+	}
+}
+
 pub fn preprocess<'a>(args: &'a [String], shorts_with_val: &'static str) -> Preprocessor<'a> {
 	Preprocessor {
 		buf: VecDeque::with_capacity(8),
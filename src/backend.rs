@@ -0,0 +1,280 @@
+#[cfg(target_os = "linux")]
+mod alsa;
+pub mod memory;
+#[cfg(unix)]
+mod pipewire;
+#[cfg(unix)]
+mod pulse;
+#[cfg(windows)]
+mod wasapi;
+
+#[cfg(unix)]
+use std::path::{
+	Path,
+	PathBuf,
+};
+use std::{
+	any::Any,
+	env,
+	io::{
+		BufRead,
+		BufReader,
+	},
+	process::{
+		Child,
+		Command,
+		Stdio,
+	},
+	sync::mpsc::Sender,
+	thread,
+	time::Duration,
+};
+
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+		Position,
+		Role,
+	},
+	error::Result,
+	session::Session,
+};
+
+/// The names accepted by `--backend` and the `WOL_BACKEND` environment variable.
+pub const NAMES: &[&str] = &[
+	#[cfg(windows)]
+	"wasapi",
+	#[cfg(unix)]
+	"pipewire",
+	#[cfg(unix)]
+	"pulse",
+	#[cfg(target_os = "linux")]
+	"alsa",
+	"memory",
+];
+
+/// How often backends without change notifications are polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Keeps a change subscription alive; dropping it unsubscribes.
+pub type Subscription = Box<dyn Any>;
+
+/// A source of audio endpoints.
+pub trait Backend {
+	fn default_device(&self, flow: Flow) -> Result<Device>;
+	fn devices(&self, flow: Flow, state: DeviceState) -> Result<Devices>;
+
+	/// Get the ID of the default device for the given role.
+	///
+	/// Backends without roles return the ID of [Backend::default_device].
+	fn default_id(&self, flow: Flow, _role: Role) -> Result<String> {
+		self.default_device(flow)?.id()
+	}
+
+	/// Subscribe to changes of the devices of the given flow, including changes of the default device.
+	///
+	/// A message is sent whenever something may have changed; it is up to the receiver to query the devices again.
+	/// An error is sent if the notifications stop.
+	/// The default implementation polls.
+	fn subscribe(&self, _flow: Flow, tx: Sender<Result<()>>) -> Result<Subscription> {
+		thread::spawn(move || {
+			while tx.send(Ok(())).is_ok() {
+				thread::sleep(POLL_INTERVAL);
+			}
+		});
+		Ok(Box::new(()))
+	}
+}
+
+/// A single audio endpoint as exposed by a [Backend].
+///
+/// Volume levels are scalars between 0.0 and 1.0.
+pub trait Endpoint {
+	fn name(&self) -> &str;
+	fn id(&self) -> Result<String>;
+	fn state(&self) -> DeviceState;
+	fn channels(&self) -> Result<u32>;
+
+	/// Get the speaker position of every channel.
+	///
+	/// The default goes by the usual layouts for the number of channels.
+	fn channel_map(&self) -> Result<Vec<Option<Position>>> {
+		Ok(Position::default_layout(self.channels()?))
+	}
+
+	/// Subscribe to changes of the levels and the mute state of this endpoint.
+	///
+	/// Backends whose [Backend::subscribe] already covers these changes don't need to implement this.
+	fn subscribe(&self, _tx: Sender<Result<()>>) -> Result<Subscription> {
+		Ok(Box::new(()))
+	}
+
+	/// Get the audio sessions of the applications using this endpoint.
+	fn sessions(&self) -> Result<Vec<Session>> {
+		Err("the backend does not support per-application volume control".into())
+	}
+
+	/// Make this endpoint the default device of its flow for every [Role].
+	fn set_default(&self) -> Result<()> {
+		Err("the backend cannot change the default device".into())
+	}
+
+	fn master_volume(&self) -> Result<f32>;
+	fn master_db(&self) -> Result<f32>;
+	fn set_master_volume(&self, volume: f32) -> Result<()>;
+
+	fn is_muted(&self) -> Result<bool>;
+	fn set_mute(&self, mute: bool) -> Result<()>;
+
+	fn channel_volume(&self, channel: u32) -> Result<f32>;
+	fn channel_db(&self, channel: u32) -> Result<f32>;
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()>;
+
+	/// Get the range of decibel levels this endpoint supports as `(min, max)`.
+	fn db_range(&self) -> Result<(f32, f32)> {
+		Err("the backend does not support decibel levels".into())
+	}
+
+	/// Convert a decibel level to a scalar the way this endpoint does.
+	///
+	/// The default treats the scalar as an amplitude; endpoints that set decibel levels natively only use this to estimate the pending levels.
+	fn db_to_scalar(&self, db: f32) -> f32 {
+		10f32.powf(db / 20.0).clamp(0.0, 1.0)
+	}
+
+	/// The inverse of [Endpoint::db_to_scalar].
+	fn scalar_to_db(&self, scalar: f32) -> f32 {
+		20.0 * scalar.log10()
+	}
+
+	fn set_master_db(&self, db: f32) -> Result<()> {
+		self.set_master_volume(self.db_to_scalar(db))
+	}
+
+	fn set_channel_db(&self, channel: u32, db: f32) -> Result<()> {
+		self.set_channel_volume(channel, self.db_to_scalar(db))
+	}
+
+	/// Set the level of every channel at once.
+	///
+	/// Backends that can do this in a single call should override this, so that the channels don't change one after the other.
+	fn set_channel_volumes(&self, volumes: &[f32]) -> Result<()> {
+		for (i, &volume) in volumes.iter().enumerate() {
+			self.set_channel_volume(i as u32, volume)?;
+		}
+		Ok(())
+	}
+}
+
+/// Create the backend with the given name.
+///
+/// If `name` is `None`, the `WOL_BACKEND` environment variable is consulted before falling back to the platform default.
+pub fn new(name: Option<&str>) -> Result<Box<dyn Backend>> {
+	let from_env = env::var("WOL_BACKEND").ok().filter(|s| !s.is_empty());
+	let name = name.or(from_env.as_deref()).unwrap_or_else(|| detect());
+
+	Ok(match name.to_lowercase().as_str() {
+		#[cfg(windows)]
+		"wasapi" => Box::new(wasapi::Wasapi),
+		#[cfg(unix)]
+		"pipewire" => Box::new(pipewire::PipeWire),
+		#[cfg(unix)]
+		"pulse" | "pulseaudio" => Box::new(pulse::Pulse),
+		#[cfg(target_os = "linux")]
+		"alsa" => Box::new(alsa::Alsa),
+		"memory" => Box::new(memory::Memory::from_env()?),
+		_ => {
+			return Err(format!(
+				"unknown backend '{name}'; available backends are: {}",
+				NAMES.join(", ")
+			)
+			.into())
+		}
+	})
+}
+
+#[cfg(windows)]
+fn detect() -> &'static str {
+	"wasapi"
+}
+
+/// Pick a backend based on which sound server sockets exist.
+#[cfg(unix)]
+fn detect() -> &'static str {
+	let runtime_dir = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+	let exists = |path: &str| runtime_dir.as_ref().is_some_and(|d| d.join(path).exists());
+
+	if exists("pipewire-0") {
+		"pipewire"
+	} else if exists("pulse/native") || env::var_os("PULSE_SERVER").is_some() {
+		"pulse"
+	} else if cfg!(target_os = "linux") && Path::new("/proc/asound/cards").exists() {
+		"alsa"
+	} else {
+		"pulse"
+	}
+}
+
+/// Run an external program and return its standard output.
+#[cfg_attr(windows, allow(dead_code))]
+fn exec(program: &str, args: &[&str]) -> Result<String> {
+	let out = Command::new(program)
+		.args(args)
+		.env("LC_ALL", "C")
+		.output()
+		.map_err(|e| format!("failed to run {program}: {e}"))?;
+
+	if !out.status.success() {
+		let stderr = String::from_utf8_lossy(&out.stderr);
+		return Err(format!("{program} exited with {}: {}", out.status, stderr.trim()).into());
+	}
+
+	String::from_utf8(out.stdout).map_err(|_| format!("{program} returned invalid UTF-8").into())
+}
+
+/// A child process that reports changes on its standard output; it's killed when dropped.
+#[cfg_attr(windows, allow(dead_code))]
+struct Monitor(Child);
+
+impl Drop for Monitor {
+	fn drop(&mut self) {
+		let _ = self.0.kill();
+		let _ = self.0.wait();
+	}
+}
+
+/// Run an external program that prints a line for each event, sending a message for every line that passes `filter`.
+///
+/// An error is sent if the program exits.
+#[cfg_attr(windows, allow(dead_code))]
+fn monitor(
+	program: &str,
+	args: &[&str],
+	filter: fn(&str) -> bool,
+	tx: Sender<Result<()>>,
+) -> Result<Subscription> {
+	let mut child = Command::new(program)
+		.args(args)
+		.env("LC_ALL", "C")
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()
+		.map_err(|e| format!("failed to run {program}: {e}"))?;
+	let stdout = child.stdout.take().expect("stdout is piped");
+	let program = program.to_string();
+
+	thread::spawn(move || {
+		for line in BufReader::new(stdout).lines().map_while(|x| x.ok()) {
+			if filter(&line) && tx.send(Ok(())).is_err() {
+				return;
+			}
+		}
+		let _ = tx.send(Err(format!("{program} stopped reporting changes").into()));
+	});
+
+	Ok(Box::new(Monitor(child)))
+}
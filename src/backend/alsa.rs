@@ -0,0 +1,647 @@
+use std::{
+	cell::{
+		OnceCell,
+		RefCell,
+	},
+	fs,
+	sync::mpsc::Sender,
+};
+
+use super::{
+	exec,
+	monitor,
+	Backend,
+	Endpoint,
+	Subscription,
+};
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+		Position,
+	},
+	error::Result,
+};
+
+/// Elements that are preferred, in order, when picking the default device.
+const DEFAULT_ELEMENTS: &[&str] = &["Master", "PCM", "Speaker", "Headphone"];
+/// Elements that are preferred, in order, when picking the default capture device.
+const DEFAULT_CAPTURE_ELEMENTS: &[&str] = &["Capture", "Mic", "Internal Mic"];
+
+/// A backend that manages the simple-mixer elements of every ALSA card through `amixer`.
+pub struct Alsa;
+
+struct Card {
+	id: String,
+	name: String,
+}
+
+struct Element {
+	flow: Flow,
+	card: String,
+	/// The element as given to `amixer sset`, e.g. `'Master',0`.
+	control: String,
+	/// The name and index of the element, e.g. `Master` and `0`.
+	element: (String, String),
+	id: String,
+	name: String,
+	min: i64,
+	max: i64,
+	// Whether the element has a playback (or capture) switch, which is what muting toggles.
+	switch: bool,
+	levels: RefCell<Vec<Level>>,
+	positions: Vec<Option<Position>>,
+	/// The decibel levels at the minimum and maximum raw levels, read on first use.
+	db_range: OnceCell<Option<(f32, f32)>>,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Level {
+	raw: i64,
+	db: Option<f32>,
+	on: Option<bool>,
+}
+
+/// Parse `/proc/asound/cards`, which has two lines per card:
+///
+/// ```text
+///  0 [Dummy          ]: Dummy - Dummy
+///                       Dummy 1
+/// ```
+fn cards() -> Result<Vec<Card>> {
+	let s = fs::read_to_string("/proc/asound/cards")
+		.map_err(|e| format!("failed to read /proc/asound/cards: {e}"))?;
+
+	Ok(s.lines()
+		.filter(|s| s.trim_start().starts_with(|c: char| c.is_ascii_digit()))
+		.filter_map(|s| {
+			let (_, rest) = s.split_once('[')?;
+			let (id, rest) = rest.split_once(']')?;
+			let name = rest.split_once(" - ").map_or(id, |x| x.1);
+			Some(Card {
+				id: id.trim().to_string(),
+				name: name.trim().to_string(),
+			})
+		})
+		.collect())
+}
+
+/// Get the part of an amixer line that belongs to the given direction.
+///
+/// E.g. `80 [80%] [on]` out of `Playback 80 [80%] [on] Capture 0 [0%] [off]`.
+fn segment(s: &str, flow: Flow) -> &str {
+	let (this, other) = match flow {
+		Flow::Render => ("Playback", "Capture"),
+		Flow::Capture => ("Capture", "Playback"),
+	};
+
+	let s = s.find(this).map_or(s, |i| &s[i + this.len()..]);
+	s.find(other).map_or(s, |i| &s[..i]).trim()
+}
+
+/// Parse the playback or capture elements out of the output of `amixer scontents` or `amixer sset`.
+fn parse_elements(card: &Card, s: &str, flow: Flow) -> Vec<Element> {
+	let (volume_cap, switch_cap, channels_prefix) = match flow {
+		Flow::Render => ("pvolume", "pswitch", "Playback channels:"),
+		Flow::Capture => ("cvolume", "cswitch", "Capture channels:"),
+	};
+
+	let mut elems = Vec::new();
+
+	for block in s.split("Simple mixer control ").skip(1) {
+		let mut lines = block.lines();
+		let Some((control, index)) = lines
+			.next()
+			.and_then(|s| s.rsplit_once(','))
+			.map(|(name, index)| (name.trim_matches('\''), index.trim()))
+		else {
+			continue;
+		};
+
+		let mut volume = false;
+		let mut switch = false;
+		let mut channels = Vec::new();
+		let mut limits = None;
+		let mut values = Vec::new();
+
+		for line in lines.map(str::trim) {
+			if let Some(caps) = line.strip_prefix("Capabilities:") {
+				let caps = caps.split_whitespace().collect::<Vec<_>>();
+				volume = caps.iter().any(|&c| c == volume_cap || c == "volume");
+				switch = caps.iter().any(|&c| c == switch_cap || c == "switch");
+			} else if let Some(chans) = line.strip_prefix(channels_prefix) {
+				channels = chans.split(" - ").map(|s| s.trim().to_string()).collect();
+			} else if line.starts_with("Playback channels:")
+				|| line.starts_with("Capture channels:")
+			{
+				// The channels of the other direction.
+			} else if let Some(l) = line.strip_prefix("Limits:") {
+				limits = segment(l, flow).split_once(" - ").and_then(|(min, max)| {
+					let min = min.trim().parse::<i64>().ok()?;
+					let max = max.split_whitespace().next()?.parse::<i64>().ok()?;
+					Some((min, max))
+				});
+			} else if let Some((chan, rest)) = line.split_once(':') {
+				values.push((chan.to_string(), parse_level(segment(rest, flow))));
+			}
+		}
+
+		let (Some((min, max)), true) = (limits, volume) else {
+			continue;
+		};
+		if max <= min {
+			continue;
+		}
+		// Elements without a "Playback channels" or "Capture channels" line have a common volume for playback and capture.
+		if channels.is_empty() {
+			channels = values.iter().map(|v| v.0.clone()).collect();
+		}
+
+		let levels = channels
+			.iter()
+			.map(|c| {
+				values
+					.iter()
+					.find(|v| &v.0 == c)
+					.and_then(|v| v.1)
+					.unwrap_or_default()
+			})
+			.collect::<Vec<_>>();
+
+		let id = if index == "0" {
+			format!("hw:{}/{control}", card.id)
+		} else {
+			format!("hw:{}/{control},{index}", card.id)
+		};
+
+		elems.push(Element {
+			flow,
+			card: card.id.clone(),
+			control: format!("'{control}',{index}"),
+			element: (control.to_string(), index.to_string()),
+			name: format!("{}: {control}", card.name),
+			id,
+			min,
+			max,
+			switch,
+			levels: RefCell::new(levels),
+			positions: channels.iter().map(|c| position(c)).collect(),
+			db_range: OnceCell::new(),
+		});
+	}
+
+	elems
+}
+
+/// Get the speaker position of a channel name such as `Front Left`.
+fn position(channel: &str) -> Option<Position> {
+	Some(match channel {
+		"Mono" => Position::Mono,
+		"Front Left" => Position::FrontLeft,
+		"Front Right" => Position::FrontRight,
+		"Front Center" => Position::FrontCenter,
+		"Woofer" => Position::Lfe,
+		"Rear Left" => Position::BackLeft,
+		"Rear Right" => Position::BackRight,
+		"Rear Center" => Position::BackCenter,
+		"Side Left" => Position::SideLeft,
+		"Side Right" => Position::SideRight,
+		_ => return None,
+	})
+}
+
+/// Parse the value of a channel line such as `80 [80%] [-20.00dB] [on]`.
+fn parse_level(s: &str) -> Option<Level> {
+	let raw = s.split_whitespace().next()?.parse::<i64>().ok()?;
+
+	let db = s
+		.split('[')
+		.filter_map(|s| s.split_once(']'))
+		.find_map(|(s, _)| s.strip_suffix("dB"))
+		.and_then(|s| s.parse::<f32>().ok());
+
+	let on = s
+		.split('[')
+		.filter_map(|s| s.split_once(']'))
+		.find_map(|(s, _)| match s {
+			"on" => Some(true),
+			"off" => Some(false),
+			_ => None,
+		});
+
+	Some(Level { raw, db, on })
+}
+
+/// Parse the decibel range out of the TLV lines of `amixer cget`, given the raw range of the control.
+///
+/// The TLV is one of these, where ranges map parts of the raw range to one of the others:
+///
+/// ```text
+///   | dBscale-min=-65.25dB,step=0.75dB,mute=0
+///   | dBminmax-min=-30.00dB,max=0.00dB
+///   | dBlinear-min=-inf,max=0.00dB
+///   | dBrange-
+///     rangemin=0,,rangemax=4
+///       | dBscale-min=-33.00dB,step=1.50dB,mute=0
+/// ```
+fn parse_db_range(s: &str, min: i64, max: i64) -> Option<(f32, f32)> {
+	let db = |s: &str| -> Option<f32> {
+		match s.trim().trim_end_matches("dB") {
+			"-inf" => Some(f32::NEG_INFINITY),
+			s => s.parse().ok(),
+		}
+	};
+	let field = |s: &str, name: &str| {
+		s.split(',')
+			.find_map(|kv| kv.trim().strip_prefix(name)?.strip_prefix('='))
+			.map(str::to_string)
+	};
+
+	let mut range = (min, max);
+	let mut out: Option<(f32, f32)> = None;
+	for line in s.lines().map(|s| s.trim().trim_start_matches('|').trim()) {
+		if line.starts_with("rangemin=") {
+			let (Some(start), Some(end)) = (field(line, "rangemin"), field(line, "rangemax"))
+			else {
+				continue;
+			};
+			range = (start.parse().ok()?, end.parse().ok()?);
+			continue;
+		}
+
+		let (lo, hi) = if let Some(s) = line.strip_prefix("dBscale-") {
+			let lo = db(&field(s, "min")?)?;
+			let step = db(&field(s, "step")?)?;
+			(lo, lo + step * (range.1 - range.0) as f32)
+		} else if let Some(s) = ["dBminmax-", "dBminmaxmute-", "dBlinear-"]
+			.iter()
+			.find_map(|p| line.strip_prefix(p))
+		{
+			(db(&field(s, "min")?)?, db(&field(s, "max")?)?)
+		} else {
+			continue;
+		};
+
+		out = Some(match out {
+			Some((a, b)) => (a.min(lo), b.max(hi)),
+			None => (lo, hi),
+		});
+	}
+
+	out
+}
+
+fn elements(flow: Flow) -> Result<Vec<Element>> {
+	let mut elems = Vec::new();
+	for card in cards()? {
+		let out = exec("amixer", &["-c", &card.id, "scontents"])?;
+		elems.extend(parse_elements(&card, &out, flow));
+	}
+
+	Ok(elems)
+}
+
+impl Backend for Alsa {
+	fn default_device(&self, flow: Flow) -> Result<Device> {
+		let mut elems = elements(flow)?;
+		// Elements of the first card come first.
+		let card = elems
+			.first()
+			.map(|e| e.card.clone())
+			.ok_or_else(|| format!("no ALSA {flow} mixer elements found"))?;
+		elems.retain(|e| e.card == card);
+
+		let preferred = match flow {
+			Flow::Render => DEFAULT_ELEMENTS,
+			Flow::Capture => DEFAULT_CAPTURE_ELEMENTS,
+		};
+		let i = preferred
+			.iter()
+			.find_map(|&name| {
+				elems
+					.iter()
+					.position(|e| e.control == format!("'{name}',0"))
+			})
+			.unwrap_or(0);
+
+		Ok(elems.swap_remove(i).into())
+	}
+
+	fn devices(&self, flow: Flow, state: DeviceState) -> Result<Devices> {
+		if !state.has(DeviceState::ACTIVE) {
+			return Ok(Box::new(std::iter::empty()));
+		}
+
+		let elems = elements(flow)?
+			.into_iter()
+			.map(Device::from)
+			.collect::<Vec<_>>();
+		Ok(Box::new(elems.into_iter()))
+	}
+
+	/// Follow `alsactl monitor`, which prints a line for every control change of every card.
+	fn subscribe(&self, _flow: Flow, tx: Sender<Result<()>>) -> Result<Subscription> {
+		monitor("alsactl", &["monitor"], |_| true, tx)
+	}
+}
+
+impl Element {
+	fn level(&self, channel: u32) -> Result<Level> {
+		self.levels
+			.borrow()
+			.get(channel as usize)
+			.copied()
+			.ok_or_else(|| format!("channel {channel} is out of range").into())
+	}
+
+	fn to_scalar(&self, raw: i64) -> f32 {
+		((raw - self.min) as f32 / (self.max - self.min) as f32).clamp(0.0, 1.0)
+	}
+
+	fn to_raw(&self, scalar: f32) -> i64 {
+		self.min + (scalar.clamp(0.0, 1.0) * (self.max - self.min) as f32).round() as i64
+	}
+
+	/// Read the decibel range of the volume control of this element with `amixer cget`.
+	fn read_db_range(&self) -> Option<(f32, f32)> {
+		let (name, index) = &self.element;
+		let suffixes = match self.flow {
+			Flow::Render => ["Playback Volume", "Volume"],
+			Flow::Capture => ["Capture Volume", "Volume"],
+		};
+
+		suffixes.iter().find_map(|suffix| {
+			let id = format!("name={name} {suffix},index={index}");
+			let out = exec("amixer", &["-c", &self.card, "cget", &id]).ok()?;
+			parse_db_range(&out, self.min, self.max)
+		})
+	}
+
+	/// Run `amixer sset` on this element and update the cached levels from its output.
+	///
+	/// amixer prints the new state of the element, which also has the updated dB values.
+	fn sset(&self, value: &str) -> Result<bool> {
+		let direction = match self.flow {
+			Flow::Render => "playback",
+			Flow::Capture => "capture",
+		};
+		let out = exec(
+			"amixer",
+			&[
+				"-c",
+				&self.card,
+				"--",
+				"sset",
+				&self.control,
+				direction,
+				value,
+			],
+		)?;
+		let card = Card {
+			id: self.card.clone(),
+			name: String::new(),
+		};
+
+		match parse_elements(&card, &out, self.flow).pop() {
+			Some(e) if e.levels.borrow().len() == self.levels.borrow().len() => {
+				self.levels.swap(&e.levels);
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+
+	/// Set levels given in decibels, where amixer picks the raw levels; without its output, the cached levels are read again.
+	fn sset_db(&self, vals: &str) -> Result<()> {
+		if !self.sset(vals)? {
+			let out = exec("amixer", &["-c", &self.card, "sget", &self.control])?;
+			let card = Card {
+				id: self.card.clone(),
+				name: String::new(),
+			};
+			if let Some(e) = parse_elements(&card, &out, self.flow).pop() {
+				self.levels.swap(&e.levels);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn apply(&self, raws: &[i64]) -> Result<()> {
+		let vals = raws
+			.iter()
+			.map(i64::to_string)
+			.collect::<Vec<_>>()
+			.join(",");
+
+		if !self.sset(&vals)? {
+			let mut levels = self.levels.borrow_mut();
+			for (level, &raw) in levels.iter_mut().zip(raws) {
+				level.raw = raw;
+				level.db = None;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Endpoint for Element {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn id(&self) -> Result<String> {
+		Ok(self.id.clone())
+	}
+
+	fn state(&self) -> DeviceState {
+		DeviceState::ACTIVE
+	}
+
+	fn channels(&self) -> Result<u32> {
+		Ok(self.levels.borrow().len() as u32)
+	}
+
+	fn channel_map(&self) -> Result<Vec<Option<Position>>> {
+		Ok(self.positions.clone())
+	}
+
+	fn master_volume(&self) -> Result<f32> {
+		let max = self.levels.borrow().iter().map(|l| l.raw).max();
+		Ok(max.map_or(0.0, |raw| self.to_scalar(raw)))
+	}
+
+	fn master_db(&self) -> Result<f32> {
+		self.levels
+			.borrow()
+			.iter()
+			.max_by_key(|l| l.raw)
+			.and_then(|l| l.db)
+			.ok_or_else(|| format!("{} has no dB information", self.name).into())
+	}
+
+	fn set_master_volume(&self, volume: f32) -> Result<()> {
+		let master = self.master_volume()?;
+		let raws = self
+			.levels
+			.borrow()
+			.iter()
+			.map(|l| {
+				if master == 0.0 {
+					self.to_raw(volume)
+				} else {
+					self.to_raw(self.to_scalar(l.raw) / master * volume)
+				}
+			})
+			.collect::<Vec<_>>();
+
+		self.apply(&raws)
+	}
+
+	/// Whether every channel's playback (or capture) switch is off.
+	fn is_muted(&self) -> Result<bool> {
+		let levels = self.levels.borrow();
+		Ok(self.switch && !levels.is_empty() && levels.iter().all(|l| l.on == Some(false)))
+	}
+
+	fn set_mute(&self, mute: bool) -> Result<()> {
+		if !self.switch {
+			return Err(format!("{} cannot be muted", self.name).into());
+		}
+
+		let value = match (self.flow, mute) {
+			(Flow::Render, true) => "mute",
+			(Flow::Render, false) => "unmute",
+			(Flow::Capture, true) => "nocap",
+			(Flow::Capture, false) => "cap",
+		};
+
+		if !self.sset(value)? {
+			let mut levels = self.levels.borrow_mut();
+			for level in levels.iter_mut() {
+				level.on = Some(!mute);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> Result<f32> {
+		self.level(channel).map(|l| self.to_scalar(l.raw))
+	}
+
+	fn channel_db(&self, channel: u32) -> Result<f32> {
+		self.level(channel)?
+			.db
+			.ok_or_else(|| format!("{} has no dB information", self.name).into())
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
+		self.level(channel)?;
+		let mut raws = self
+			.levels
+			.borrow()
+			.iter()
+			.map(|l| l.raw)
+			.collect::<Vec<_>>();
+		raws[channel as usize] = self.to_raw(volume);
+		self.apply(&raws)
+	}
+
+	fn db_range(&self) -> Result<(f32, f32)> {
+		self.db_range
+			.get_or_init(|| self.read_db_range())
+			.ok_or_else(|| format!("{} has no dB information", self.name).into())
+	}
+
+	/// Estimate the scalar of a decibel level, treating the decibels as linear in the raw level, like most ALSA controls.
+	fn db_to_scalar(&self, db: f32) -> f32 {
+		match self.db_range() {
+			Ok((min, max)) if min.is_finite() && max > min => {
+				((db - min) / (max - min)).clamp(0.0, 1.0)
+			}
+			_ => 10f32.powf(db / 20.0).clamp(0.0, 1.0),
+		}
+	}
+
+	fn scalar_to_db(&self, scalar: f32) -> f32 {
+		match self.db_range() {
+			Ok((min, max)) if min.is_finite() && max > min => min + scalar * (max - min),
+			_ => 20.0 * scalar.log10(),
+		}
+	}
+
+	/// Move every channel by the same number of decibels, which keeps their ratios; amixer converts the decibels itself.
+	fn set_master_db(&self, db: f32) -> Result<()> {
+		self.db_range()?;
+		let master = self.master_db()?;
+		let vals = self
+			.levels
+			.borrow()
+			.iter()
+			.map(|l| l.db.map(|x| format!("{:.2}dB", x + db - master)))
+			.collect::<Option<Vec<_>>>();
+
+		match vals {
+			Some(vals) => self.sset_db(&vals.join(",")),
+			None => self.set_master_volume(self.db_to_scalar(db)),
+		}
+	}
+
+	fn set_channel_db(&self, channel: u32, db: f32) -> Result<()> {
+		self.db_range()?;
+		self.level(channel)?;
+		let vals = self
+			.levels
+			.borrow()
+			.iter()
+			.enumerate()
+			.map(|(c, l)| {
+				if c == channel as usize {
+					format!("{db:.2}dB")
+				} else {
+					l.raw.to_string()
+				}
+			})
+			.collect::<Vec<_>>();
+		self.sset_db(&vals.join(","))
+	}
+
+	fn set_channel_volumes(&self, volumes: &[f32]) -> Result<()> {
+		if volumes.len() != self.levels.borrow().len() {
+			return Err("the number of levels does not match the number of channels".into());
+		}
+		let raws = volumes.iter().map(|&v| self.to_raw(v)).collect::<Vec<_>>();
+		self.apply(&raws)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn db_scale() {
+		let s = "numid=1,iface=MIXER,name='Master Playback Volume'
+  ; type=INTEGER,access=rw---R--,values=2,min=0,max=87,step=0
+  : values=60,60
+  | dBscale-min=-65.25dB,step=0.75dB,mute=0
+";
+		assert_eq!(parse_db_range(s, 0, 87), Some((-65.25, 0.0)));
+	}
+
+	#[test]
+	fn db_ranges() {
+		let s = "  | dBrange-
+    rangemin=0,,rangemax=4
+      | dBscale-min=-33.00dB,step=1.50dB,mute=1
+    rangemin=5,,rangemax=20
+      | dBminmax-min=-27.00dB,max=6.00dB
+";
+		assert_eq!(parse_db_range(s, 0, 20), Some((-33.0, 6.0)));
+		assert_eq!(parse_db_range("  : values=3", 0, 20), None);
+	}
+}
@@ -0,0 +1,394 @@
+use std::{
+	cell::{
+		Cell,
+		RefCell,
+	},
+	env,
+	rc::Rc,
+};
+
+use super::{
+	Backend,
+	Endpoint,
+};
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+	},
+	error::Result,
+	session::{
+		Session,
+		SessionState,
+	},
+};
+
+/// The lowest level in decibels reported by the in-memory devices; a scalar of 0 maps to this.
+const MIN_DB: f32 = -65.25;
+
+/// A backend that keeps its devices in memory.
+///
+/// The devices are described with the `WOL_MEMORY_DEVICES` environment variable, which is a `;` separated list of entries with the syntax `<name>[:<flag>,<flag>...]=<level>,<level>...`.
+/// Each level is a percentage and there is one per channel.
+/// A flag is either a state (`active` (the default), `disabled`, `notpresent` or `unplugged`), `muted`, `capture` (the device is an input device) or `default`.
+/// Unless a device of the same flow is flagged `default`, the first active device of each flow is the default device.
+///
+/// E.g. `Speakers=50,50;Headset:disabled,muted=20,30;Microphone:capture=80`
+///
+/// Application sessions are described with the `WOL_MEMORY_SESSIONS` environment variable, which has the same syntax except that each name is `<device name>/<process name>`.
+/// A session flag is either a state (`active` (the default), `inactive` or `expired`) or `muted`.
+/// Sessions get the process IDs 1000, 1001 and so on, in order.
+///
+/// E.g. `Speakers/firefox=50,50;Speakers/mpv:inactive=100,100`
+pub struct Memory {
+	devices: Rc<[Slot]>,
+}
+
+struct Slot {
+	flow: Flow,
+	name: String,
+	id: String,
+	state: DeviceState,
+	// The index of the device a session belongs to; None for devices.
+	owner: Option<usize>,
+	session_state: SessionState,
+	default: Cell<bool>,
+	muted: Cell<bool>,
+	levels: RefCell<Vec<f32>>,
+}
+
+struct MemoryDevice {
+	devices: Rc<[Slot]>,
+	index: usize,
+}
+
+impl Memory {
+	pub fn from_env() -> Result<Self> {
+		let mut devices = match env::var("WOL_MEMORY_DEVICES") {
+			Ok(s) => Self::parse(&s)?,
+			Err(_) => Vec::new(),
+		};
+		if let Ok(s) = env::var("WOL_MEMORY_SESSIONS") {
+			Self::parse_sessions(&s, &mut devices)?;
+		}
+
+		Ok(Self {
+			devices: devices.into(),
+		})
+	}
+
+	/// Create the devices described by a `WOL_MEMORY_DEVICES` string, without sessions.
+	#[cfg(test)]
+	pub fn new(devices: &str) -> Result<Self> {
+		Ok(Self {
+			devices: Self::parse(devices)?.into(),
+		})
+	}
+
+	fn parse(spec: &str) -> Result<Vec<Slot>> {
+		let mut devices = Vec::new();
+
+		for (i, entry) in entries(spec).enumerate() {
+			let (head, levels) = entry.rsplit_once('=').ok_or_else(|| {
+				format!("WOL_MEMORY_DEVICES: missing channel levels for the entry '{entry}'")
+			})?;
+			let (name, flags) = head.rsplit_once(':').unwrap_or((head, ""));
+			let mut state = DeviceState::ACTIVE;
+			let mut muted = false;
+			let mut default = false;
+			let mut flow = Flow::Render;
+			for flag in flags.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+				if flag.eq_ignore_ascii_case("muted") {
+					muted = true;
+				} else if flag.eq_ignore_ascii_case("capture") {
+					flow = Flow::Capture;
+				} else if flag.eq_ignore_ascii_case("default") {
+					default = true;
+				} else {
+					state = parse_state(flag)?;
+				}
+			}
+
+			let levels = parse_levels("WOL_MEMORY_DEVICES", name, levels)?;
+
+			devices.push(Slot {
+				flow,
+				name: name.trim().to_string(),
+				id: format!("memory:{i}"),
+				state,
+				owner: None,
+				session_state: SessionState::Active,
+				default: Cell::new(default),
+				muted: Cell::new(muted),
+				levels: RefCell::new(levels),
+			});
+		}
+
+		for flow in [Flow::Render, Flow::Capture] {
+			if !devices.iter().any(|d| d.flow == flow && d.default.get()) {
+				if let Some(d) = devices
+					.iter()
+					.find(|d| d.flow == flow && d.state == DeviceState::ACTIVE)
+				{
+					d.default.set(true);
+				}
+			}
+		}
+
+		Ok(devices)
+	}
+
+	fn parse_sessions(spec: &str, devices: &mut Vec<Slot>) -> Result<()> {
+		for (i, entry) in entries(spec).enumerate() {
+			let (head, levels) = entry.rsplit_once('=').ok_or_else(|| {
+				format!("WOL_MEMORY_SESSIONS: missing channel levels for the entry '{entry}'")
+			})?;
+			let (name, flags) = head.rsplit_once(':').unwrap_or((head, ""));
+			let (device, process) = name.split_once('/').ok_or_else(|| {
+				format!("WOL_MEMORY_SESSIONS: missing a device name for the entry '{entry}'")
+			})?;
+			let owner = devices
+				.iter()
+				.position(|d| d.owner.is_none() && d.name == device.trim())
+				.ok_or_else(|| format!("WOL_MEMORY_SESSIONS: no device named '{device}'"))?;
+
+			let mut state = SessionState::Active;
+			let mut muted = false;
+			for flag in flags.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+				state = match flag.to_lowercase().as_str() {
+					"muted" => {
+						muted = true;
+						continue;
+					}
+					"active" => SessionState::Active,
+					"inactive" => SessionState::Inactive,
+					"expired" => SessionState::Expired,
+					_ => {
+						return Err(
+							format!("WOL_MEMORY_SESSIONS: unknown session state '{flag}'").into(),
+						)
+					}
+				};
+			}
+
+			let levels = parse_levels("WOL_MEMORY_SESSIONS", name, levels)?;
+
+			devices.push(Slot {
+				flow: devices[owner].flow,
+				name: process.trim().to_string(),
+				id: (1000 + i).to_string(),
+				state: DeviceState::ACTIVE,
+				owner: Some(owner),
+				session_state: state,
+				default: Cell::new(false),
+				muted: Cell::new(muted),
+				levels: RefCell::new(levels),
+			});
+		}
+
+		Ok(())
+	}
+}
+
+fn entries(spec: &str) -> impl Iterator<Item = &str> {
+	spec.split(';').map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn parse_levels(var: &str, name: &str, levels: &str) -> Result<Vec<f32>> {
+	levels
+		.split(',')
+		.map(|s| match s.trim().parse::<f32>() {
+			Ok(n) if (0.0..=100.0).contains(&n) => Ok(n / 100.0),
+			_ => Err(format!(
+				"{var}: invalid level '{s}' for '{name}'; levels must be numbers from 0 to 100"
+			)
+			.into()),
+		})
+		.collect()
+}
+
+fn parse_state(s: &str) -> Result<DeviceState> {
+	Ok(match s.trim().to_lowercase().as_str() {
+		"active" => DeviceState::ACTIVE,
+		"disabled" => DeviceState::DISABLED,
+		"notpresent" => DeviceState::NOT_PRESENT,
+		"unplugged" => DeviceState::UNPLUGGED,
+		_ => return Err(format!("WOL_MEMORY_DEVICES: unknown device state '{s}'").into()),
+	})
+}
+
+fn to_db(scalar: f32) -> f32 {
+	if scalar <= 0.0 {
+		MIN_DB
+	} else {
+		f32::max(20.0 * scalar.log10(), MIN_DB)
+	}
+}
+
+impl Backend for Memory {
+	fn default_device(&self, flow: Flow) -> Result<Device> {
+		self.devices
+			.iter()
+			.position(|d| d.owner.is_none() && d.flow == flow && d.default.get())
+			.map(|index| self.device(index).into())
+			.ok_or_else(|| format!("the memory backend has no default {flow} device").into())
+	}
+
+	fn devices(&self, flow: Flow, state: DeviceState) -> Result<Devices> {
+		let devices = self
+			.devices
+			.iter()
+			.enumerate()
+			.filter(|(_, d)| d.owner.is_none() && d.flow == flow && state.has(d.state))
+			.map(|(index, _)| Device::from(self.device(index)))
+			.collect::<Vec<_>>();
+
+		Ok(Box::new(devices.into_iter()))
+	}
+}
+
+impl Memory {
+	fn device(&self, index: usize) -> MemoryDevice {
+		MemoryDevice {
+			devices: Rc::clone(&self.devices),
+			index,
+		}
+	}
+}
+
+impl MemoryDevice {
+	fn slot(&self) -> &Slot {
+		&self.devices[self.index]
+	}
+
+	fn level(&self, channel: u32) -> Result<f32> {
+		self.slot()
+			.levels
+			.borrow()
+			.get(channel as usize)
+			.copied()
+			.ok_or_else(|| format!("channel {channel} is out of range").into())
+	}
+}
+
+impl Endpoint for MemoryDevice {
+	fn name(&self) -> &str {
+		&self.slot().name
+	}
+
+	fn id(&self) -> Result<String> {
+		Ok(self.slot().id.clone())
+	}
+
+	fn state(&self) -> DeviceState {
+		self.slot().state
+	}
+
+	fn sessions(&self) -> Result<Vec<Session>> {
+		Ok(self
+			.devices
+			.iter()
+			.enumerate()
+			.filter(|(_, d)| d.owner == Some(self.index))
+			.map(|(index, d)| {
+				let control = MemoryDevice {
+					devices: Rc::clone(&self.devices),
+					index,
+				};
+				Session::new(
+					d.id.parse().unwrap_or_default(),
+					d.name.clone(),
+					d.session_state,
+					control.into(),
+				)
+			})
+			.collect())
+	}
+
+	fn set_default(&self) -> Result<()> {
+		if self.slot().owner.is_some() {
+			return Err("an application cannot be the default device".into());
+		}
+		let flow = self.slot().flow;
+		for (i, d) in self.devices.iter().enumerate() {
+			if d.owner.is_none() && d.flow == flow {
+				d.default.set(i == self.index);
+			}
+		}
+		Ok(())
+	}
+
+	fn channels(&self) -> Result<u32> {
+		Ok(self.slot().levels.borrow().len() as u32)
+	}
+
+	fn master_volume(&self) -> Result<f32> {
+		Ok(self
+			.slot()
+			.levels
+			.borrow()
+			.iter()
+			.copied()
+			.max_by(f32::total_cmp)
+			.unwrap_or(0.0))
+	}
+
+	fn master_db(&self) -> Result<f32> {
+		self.master_volume().map(to_db)
+	}
+
+	fn set_master_volume(&self, volume: f32) -> Result<()> {
+		let volume = volume.clamp(0.0, 1.0);
+		let master = self.master_volume()?;
+		let mut levels = self.slot().levels.borrow_mut();
+
+		if master == 0.0 {
+			levels.iter_mut().for_each(|n| *n = volume);
+		} else {
+			levels.iter_mut().for_each(|n| *n = *n / master * volume);
+		}
+
+		Ok(())
+	}
+
+	fn is_muted(&self) -> Result<bool> {
+		Ok(self.slot().muted.get())
+	}
+
+	fn set_mute(&self, mute: bool) -> Result<()> {
+		self.slot().muted.set(mute);
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> Result<f32> {
+		self.level(channel)
+	}
+
+	fn channel_db(&self, channel: u32) -> Result<f32> {
+		self.level(channel).map(to_db)
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
+		self.level(channel)?;
+		self.slot().levels.borrow_mut()[channel as usize] = volume.clamp(0.0, 1.0);
+		Ok(())
+	}
+
+	fn db_range(&self) -> Result<(f32, f32)> {
+		Ok((MIN_DB, 0.0))
+	}
+
+	fn db_to_scalar(&self, db: f32) -> f32 {
+		if db <= MIN_DB {
+			0.0
+		} else {
+			10f32.powf(db / 20.0).clamp(0.0, 1.0)
+		}
+	}
+
+	fn scalar_to_db(&self, scalar: f32) -> f32 {
+		to_db(scalar)
+	}
+}
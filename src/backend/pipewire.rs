@@ -0,0 +1,526 @@
+use std::{
+	cell::{
+		Cell,
+		RefCell,
+	},
+	sync::mpsc::Sender,
+};
+
+use serde_json::Value as Json;
+
+use super::{
+	exec,
+	monitor,
+	Backend,
+	Endpoint,
+	Subscription,
+};
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+		Position,
+	},
+	error::Result,
+	session::{
+		Session,
+		SessionState,
+	},
+};
+
+/// A backend that manages PipeWire `Audio/Sink` and `Audio/Source` nodes through `pw-dump`, `pw-cli` and `pw-metadata`.
+pub struct PipeWire;
+
+/// The media classes of the nodes that have a volume.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Class {
+	Sink,
+	Source,
+	OutputStream,
+	InputStream,
+}
+
+struct Node {
+	class: Class,
+	// The global object ID, used with pw-cli.
+	object: u64,
+	// The node.name property.
+	id: String,
+	name: String,
+	state: DeviceState,
+	// The mute property.
+	muted: Cell<bool>,
+	// The channelVolumes property; these are linear factors, not the cubic volumes shown in the user interfaces.
+	volumes: RefCell<Vec<f32>>,
+	// The channelMap property.
+	positions: Vec<Option<Position>>,
+}
+
+fn dump() -> Result<Vec<Json>> {
+	let out = exec("pw-dump", &[])?;
+	match serde_json::from_str(&out) {
+		Ok(Json::Array(objects)) => Ok(objects),
+		Ok(_) => Err("unexpected output from pw-dump".into()),
+		Err(e) => Err(format!("failed to parse the output of pw-dump: {e}").into()),
+	}
+}
+
+impl Class {
+	fn of(flow: Flow) -> Self {
+		match flow {
+			Flow::Render => Self::Sink,
+			Flow::Capture => Self::Source,
+		}
+	}
+
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Sink => "Audio/Sink",
+			Self::Source => "Audio/Source",
+			Self::OutputStream => "Stream/Output/Audio",
+			Self::InputStream => "Stream/Input/Audio",
+		}
+	}
+}
+
+fn parse_node(json: &Json, class: Class) -> Option<Node> {
+	let info = &json["info"];
+	let props = &info["props"];
+	if props["media.class"].as_str() != Some(class.as_str()) {
+		return None;
+	}
+
+	let object = json["id"].as_u64()?;
+	let id = props["node.name"].as_str()?.to_string();
+	let name = props["node.description"]
+		.as_str()
+		.or_else(|| props["node.nick"].as_str())
+		.unwrap_or(&id)
+		.to_string();
+
+	let state = match info["state"].as_str() {
+		Some("error") => DeviceState::NOT_PRESENT,
+		_ => DeviceState::ACTIVE,
+	};
+
+	let params = info["params"]["Props"].as_array();
+	let muted = params
+		.and_then(|params| params.iter().find_map(|p| p["mute"].as_bool()))
+		.unwrap_or(false);
+	let volumes = params
+		.and_then(|params| params.iter().find_map(|p| p["channelVolumes"].as_array()))
+		.map(|vols| {
+			vols.iter()
+				.map(|v| v.as_f64().unwrap_or(0.0) as f32)
+				.collect()
+		})
+		.unwrap_or_default();
+	let positions = params
+		.and_then(|params| params.iter().find_map(|p| p["channelMap"].as_array()))
+		.map(|map| {
+			map.iter()
+				.map(|p| p.as_str().and_then(Position::parse))
+				.collect()
+		})
+		.unwrap_or_default();
+
+	Some(Node {
+		class,
+		object,
+		id,
+		name,
+		state,
+		muted: Cell::new(muted),
+		volumes: RefCell::new(volumes),
+		positions,
+	})
+}
+
+/// Parse a stream node into a session.
+fn parse_stream(json: &Json, class: Class) -> Option<Session> {
+	let info = &json["info"];
+	let props = &info["props"];
+	let pid = match &props["application.process.id"] {
+		Json::String(s) => s.parse::<u32>().ok(),
+		x => x.as_u64().map(|n| n as u32),
+	}
+	.unwrap_or(0);
+	let process = props["application.process.binary"]
+		.as_str()
+		.or_else(|| props["application.name"].as_str())
+		.unwrap_or_default()
+		.to_string();
+	let state = match info["state"].as_str() {
+		Some("running") => SessionState::Active,
+		_ => SessionState::Inactive,
+	};
+
+	let mut node = parse_node(json, class)?;
+	node.name = process.clone();
+	Some(Session::new(pid, process, state, node.into()))
+}
+
+/// Get the node.name of the default sink or source from the "default" metadata object.
+fn default_node(objects: &[Json], flow: Flow) -> Option<&str> {
+	let metadata = objects.iter().find(|o| {
+		o["type"].as_str() == Some("PipeWire:Interface:Metadata")
+			&& o["props"]["metadata.name"].as_str() == Some("default")
+	})?["metadata"]
+		.as_array()?;
+
+	let keys = match flow {
+		Flow::Render => ["default.audio.sink", "default.configured.audio.sink"],
+		Flow::Capture => ["default.audio.source", "default.configured.audio.source"],
+	};
+
+	keys.iter()
+		.find_map(|key| metadata.iter().find(|m| m["key"].as_str() == Some(key)))
+		.and_then(|m| m["value"]["name"].as_str())
+}
+
+fn to_scalar(linear: f32) -> f32 {
+	linear.cbrt().clamp(0.0, 1.0)
+}
+
+fn to_linear(scalar: f32) -> f32 {
+	scalar.clamp(0.0, 1.0).powi(3)
+}
+
+fn to_db(scalar: f32) -> f32 {
+	20.0 * to_linear(scalar).log10()
+}
+
+impl Backend for PipeWire {
+	fn default_device(&self, flow: Flow) -> Result<Device> {
+		let kind = match flow {
+			Flow::Render => "sink",
+			Flow::Capture => "source",
+		};
+		let objects = dump()?;
+		let default = default_node(&objects, flow)
+			.ok_or_else(|| format!("PipeWire has no default {kind}"))?;
+
+		objects
+			.iter()
+			.filter_map(|x| parse_node(x, Class::of(flow)))
+			.find(|n| n.id == default)
+			.map(Device::from)
+			.ok_or_else(|| format!("the default {kind} {default} was not found").into())
+	}
+
+	fn devices(&self, flow: Flow, state: DeviceState) -> Result<Devices> {
+		let nodes = dump()?
+			.iter()
+			.filter_map(|x| parse_node(x, Class::of(flow)))
+			.filter(|n| state.has(n.state))
+			.map(Device::from)
+			.collect::<Vec<_>>();
+
+		Ok(Box::new(nodes.into_iter()))
+	}
+
+	/// Follow `pw-dump --monitor`, which prints the changed objects as they change.
+	fn subscribe(&self, _flow: Flow, tx: Sender<Result<()>>) -> Result<Subscription> {
+		monitor("pw-dump", &["--monitor"], |_| true, tx)
+	}
+}
+
+impl Node {
+	fn linear(&self, channel: u32) -> Result<f32> {
+		self.volumes
+			.borrow()
+			.get(channel as usize)
+			.copied()
+			.ok_or_else(|| format!("channel {channel} is out of range").into())
+	}
+
+	fn set_props(&self, props: &str) -> Result<()> {
+		let object = self.object.to_string();
+		exec("pw-cli", &["set-param", &object, "Props", props])?;
+		Ok(())
+	}
+
+	fn apply(&self, volumes: Vec<f32>) -> Result<()> {
+		let vals = volumes
+			.iter()
+			.map(f32::to_string)
+			.collect::<Vec<_>>()
+			.join(", ");
+		self.set_props(&format!("{{ \"channelVolumes\": [{vals}] }}"))?;
+
+		*self.volumes.borrow_mut() = volumes;
+		Ok(())
+	}
+}
+
+impl Endpoint for Node {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn id(&self) -> Result<String> {
+		Ok(self.id.clone())
+	}
+
+	fn state(&self) -> DeviceState {
+		self.state
+	}
+
+	/// Get the streams linked to this node.
+	fn sessions(&self) -> Result<Vec<Session>> {
+		let class = match self.class {
+			Class::Sink => Class::OutputStream,
+			Class::Source => Class::InputStream,
+			Class::OutputStream | Class::InputStream => return Ok(Vec::new()),
+		};
+
+		let objects = dump()?;
+		let linked = |stream: u64| {
+			let (output, input) = match class {
+				Class::OutputStream => (stream, self.object),
+				_ => (self.object, stream),
+			};
+			objects.iter().any(|o| {
+				o["type"].as_str() == Some("PipeWire:Interface:Link")
+					&& o["info"]["output-node-id"].as_u64() == Some(output)
+					&& o["info"]["input-node-id"].as_u64() == Some(input)
+			})
+		};
+
+		Ok(objects
+			.iter()
+			.filter(|o| o["id"].as_u64().is_some_and(linked))
+			.filter_map(|o| parse_stream(o, class))
+			.collect())
+	}
+
+	/// Set the configured default, which is what the session manager persists and follows.
+	fn set_default(&self) -> Result<()> {
+		let key = match self.class {
+			Class::Sink => "default.configured.audio.sink",
+			Class::Source => "default.configured.audio.source",
+			Class::OutputStream | Class::InputStream => {
+				return Err("an application cannot be the default device".into())
+			}
+		};
+		let value = serde_json::json!({ "name": self.id }).to_string();
+		exec(
+			"pw-metadata",
+			&["-n", "default", "0", key, &value, "Spa:String:JSON"],
+		)?;
+		Ok(())
+	}
+
+	fn channels(&self) -> Result<u32> {
+		Ok(self.volumes.borrow().len() as u32)
+	}
+
+	fn channel_map(&self) -> Result<Vec<Option<Position>>> {
+		Ok(self.positions.clone())
+	}
+
+	fn master_volume(&self) -> Result<f32> {
+		let max = self
+			.volumes
+			.borrow()
+			.iter()
+			.copied()
+			.max_by(f32::total_cmp)
+			.unwrap_or(0.0);
+		Ok(to_scalar(max))
+	}
+
+	fn master_db(&self) -> Result<f32> {
+		self.master_volume().map(to_db)
+	}
+
+	fn set_master_volume(&self, volume: f32) -> Result<()> {
+		let master = self.master_volume()?;
+		let volumes = self
+			.volumes
+			.borrow()
+			.iter()
+			.map(|&linear| {
+				if master == 0.0 {
+					to_linear(volume)
+				} else {
+					to_linear(to_scalar(linear) / master * volume)
+				}
+			})
+			.collect();
+
+		self.apply(volumes)
+	}
+
+	fn is_muted(&self) -> Result<bool> {
+		Ok(self.muted.get())
+	}
+
+	fn set_mute(&self, mute: bool) -> Result<()> {
+		self.set_props(&format!("{{ \"mute\": {mute} }}"))?;
+		self.muted.set(mute);
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> Result<f32> {
+		self.linear(channel).map(to_scalar)
+	}
+
+	fn channel_db(&self, channel: u32) -> Result<f32> {
+		self.channel_volume(channel).map(to_db)
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
+		self.linear(channel)?;
+		let mut volumes = self.volumes.borrow().clone();
+		volumes[channel as usize] = to_linear(volume);
+		self.apply(volumes)
+	}
+
+	fn set_channel_volumes(&self, volumes: &[f32]) -> Result<()> {
+		if volumes.len() != self.volumes.borrow().len() {
+			return Err("the number of levels does not match the number of channels".into());
+		}
+		self.apply(volumes.iter().map(|&v| to_linear(v)).collect())
+	}
+
+	fn db_range(&self) -> Result<(f32, f32)> {
+		Ok((f32::NEG_INFINITY, 0.0))
+	}
+
+	fn db_to_scalar(&self, db: f32) -> f32 {
+		to_scalar(10f32.powf(db / 20.0))
+	}
+
+	fn scalar_to_db(&self, scalar: f32) -> f32 {
+		to_db(scalar)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	/// Objects as listed by `pw-dump`, trimmed down.
+	fn objects() -> Vec<Json> {
+		let json = json!([
+			{
+				"id": 0,
+				"type": "PipeWire:Interface:Metadata",
+				"props": {"metadata.name": "default"},
+				"metadata": [
+					{"subject": 0, "key": "default.configured.audio.sink", "type": "Spa:String:JSON", "value": {"name": "alsa_output.usb"}},
+					{"subject": 0, "key": "default.audio.sink", "type": "Spa:String:JSON", "value": {"name": "alsa_output.pci"}},
+					{"subject": 0, "key": "default.audio.source", "type": "Spa:String:JSON", "value": {"name": "alsa_input.pci"}}
+				]
+			},
+			{
+				"id": 48,
+				"type": "PipeWire:Interface:Node",
+				"info": {
+					"state": "running",
+					"props": {
+						"media.class": "Audio/Sink",
+						"node.name": "alsa_output.pci",
+						"node.description": "Built-in Audio Analog Surround 2.1"
+					},
+					"params": {
+						"Props": [
+							{"volume": 1.0, "mute": true, "channelVolumes": [0.125, 1.0, 0.0], "channelMap": ["FL", "FR", "LFE"]},
+							{"params": []}
+						]
+					}
+				}
+			},
+			{
+				"id": 52,
+				"type": "PipeWire:Interface:Node",
+				"info": {
+					"state": "error",
+					"props": {"media.class": "Audio/Source", "node.name": "alsa_input.pci", "node.nick": "Mic"},
+					"params": {"Props": [{"channelVolumes": [0.5], "channelMap": ["MONO"]}]}
+				}
+			},
+			{
+				"id": 60,
+				"type": "PipeWire:Interface:Node",
+				"info": {
+					"state": "running",
+					"props": {
+						"media.class": "Stream/Output/Audio",
+						"node.name": "firefox",
+						"application.name": "Firefox",
+						"application.process.id": "4242"
+					},
+					"params": {"Props": [{"channelVolumes": [1.0, 1.0], "channelMap": ["FL", "XX"]}]}
+				}
+			}
+		]);
+		match json {
+			Json::Array(objects) => objects,
+			_ => unreachable!(),
+		}
+	}
+
+	#[test]
+	fn nodes() {
+		let objects = objects();
+		let sink = parse_node(&objects[1], Class::Sink).unwrap();
+		assert!(parse_node(&objects[1], Class::Source).is_none());
+		assert_eq!(sink.object, 48);
+		assert_eq!(sink.id, "alsa_output.pci");
+		assert_eq!(sink.name, "Built-in Audio Analog Surround 2.1");
+		assert_eq!(sink.state, DeviceState::ACTIVE);
+		assert!(sink.is_muted().unwrap());
+		assert_eq!(sink.channels().unwrap(), 3);
+		assert_eq!(
+			sink.channel_map().unwrap(),
+			[
+				Some(Position::FrontLeft),
+				Some(Position::FrontRight),
+				Some(Position::Lfe)
+			]
+		);
+		assert_eq!(sink.channel_volume(0).unwrap(), 0.5);
+		assert_eq!(sink.channel_volume(2).unwrap(), 0.0);
+		assert_eq!(sink.master_volume().unwrap(), 1.0);
+
+		let source = parse_node(&objects[2], Class::Source).unwrap();
+		assert_eq!(source.name, "Mic");
+		assert_eq!(source.state, DeviceState::NOT_PRESENT);
+		assert!(!source.is_muted().unwrap());
+		assert_eq!(source.channel_map().unwrap(), [Some(Position::Mono)]);
+	}
+
+	#[test]
+	fn streams() {
+		let objects = objects();
+		let session = parse_stream(&objects[3], Class::OutputStream).unwrap();
+		assert_eq!(session.pid(), 4242);
+		assert_eq!(session.process(), "Firefox");
+		assert_eq!(session.state(), SessionState::Active);
+		assert_eq!(
+			session.control().channel_map().unwrap(),
+			[Some(Position::FrontLeft), None]
+		);
+		assert!(parse_stream(&objects[1], Class::OutputStream).is_none());
+	}
+
+	#[test]
+	fn defaults() {
+		let objects = objects();
+		assert_eq!(
+			default_node(&objects, Flow::Render),
+			Some("alsa_output.pci")
+		);
+		assert_eq!(
+			default_node(&objects, Flow::Capture),
+			Some("alsa_input.pci")
+		);
+		assert_eq!(default_node(&objects[1..], Flow::Render), None);
+	}
+}
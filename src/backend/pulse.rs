@@ -0,0 +1,533 @@
+use std::{
+	cell::{
+		Cell,
+		RefCell,
+	},
+	sync::mpsc::Sender,
+};
+
+use serde_json::Value as Json;
+
+use super::{
+	exec,
+	monitor,
+	Backend,
+	Endpoint,
+	Subscription,
+};
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+		Position,
+	},
+	error::Result,
+	session::{
+		Session,
+		SessionState,
+	},
+};
+
+/// `PA_VOLUME_NORM`; the raw volume that corresponds to 100%.
+const VOLUME_NORM: f32 = 65536.0;
+
+/// A backend that manages PulseAudio sinks and sources through `pactl`.
+pub struct Pulse;
+
+/// The kinds of PulseAudio objects that have a volume.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Kind {
+	Sink,
+	Source,
+	SinkInput,
+	SourceOutput,
+}
+
+/// A sink, a source or a stream connected to one.
+struct PulseDevice {
+	kind: Kind,
+	index: u64,
+	// The sink or source name, or the stream index.
+	id: String,
+	// The sink or source description, or the stream's process name.
+	name: String,
+	state: DeviceState,
+	muted: Cell<bool>,
+	// Raw volumes in channel map order.
+	volumes: RefCell<Vec<u32>>,
+	positions: Vec<Option<Position>>,
+}
+
+fn pactl(args: &[&str]) -> Result<Json> {
+	let out = exec("pactl", &[&["--format=json"], args].concat())?;
+	serde_json::from_str(&out)
+		.map_err(|e| format!("failed to parse the output of pactl: {e}").into())
+}
+
+impl Kind {
+	fn of(flow: Flow) -> Self {
+		match flow {
+			Flow::Render => Self::Sink,
+			Flow::Capture => Self::Source,
+		}
+	}
+
+	/// The name used in pactl commands.
+	fn as_str(self) -> &'static str {
+		match self {
+			Self::Sink => "sink",
+			Self::Source => "source",
+			Self::SinkInput => "sink-input",
+			Self::SourceOutput => "source-output",
+		}
+	}
+}
+
+/// Read the volume object in channel map order.
+fn parse_volumes(json: &Json) -> Vec<u32> {
+	// The volume object is keyed by channel position and serde_json doesn't preserve the key order, so go through the channel map instead.
+	json["channel_map"]
+		.as_str()
+		.unwrap_or_default()
+		.split(',')
+		.filter(|s| !s.is_empty())
+		.map(|pos| {
+			json["volume"][pos]["value"]
+				.as_u64()
+				.map_or(0, |n| n as u32)
+		})
+		.collect()
+}
+
+/// Read the speaker positions out of the channel map.
+fn parse_positions(json: &Json) -> Vec<Option<Position>> {
+	json["channel_map"]
+		.as_str()
+		.unwrap_or_default()
+		.split(',')
+		.filter(|s| !s.is_empty())
+		.map(|pos| {
+			Some(match pos {
+				"mono" => Position::Mono,
+				"front-left" => Position::FrontLeft,
+				"front-right" => Position::FrontRight,
+				"front-center" => Position::FrontCenter,
+				"lfe" => Position::Lfe,
+				"rear-left" => Position::BackLeft,
+				"rear-right" => Position::BackRight,
+				"front-left-of-center" => Position::FrontLeftCenter,
+				"front-right-of-center" => Position::FrontRightCenter,
+				"rear-center" => Position::BackCenter,
+				"side-left" => Position::SideLeft,
+				"side-right" => Position::SideRight,
+				_ => return None,
+			})
+		})
+		.collect()
+}
+
+fn parse_device(json: &Json, flow: Flow) -> Option<PulseDevice> {
+	// Skip the monitor sources of sinks.
+	if json["monitor_of_sink"]
+		.as_str()
+		.is_some_and(|s| !s.is_empty() && s != "n/a")
+	{
+		return None;
+	}
+
+	let index = json["index"].as_u64()?;
+	let id = json["name"].as_str()?.to_string();
+	let name = json["description"].as_str().unwrap_or_default().to_string();
+
+	let unplugged = json["active_port"]
+		.as_str()
+		.and_then(|active| {
+			json["ports"]
+				.as_array()?
+				.iter()
+				.find(|p| p["name"].as_str() == Some(active))
+		})
+		.is_some_and(|p| p["availability"].as_str() == Some("not available"));
+
+	let state = match json["state"].as_str() {
+		_ if unplugged => DeviceState::UNPLUGGED,
+		Some("RUNNING" | "IDLE" | "SUSPENDED") => DeviceState::ACTIVE,
+		_ => DeviceState::NOT_PRESENT,
+	};
+
+	Some(PulseDevice {
+		kind: Kind::of(flow),
+		index,
+		id,
+		name,
+		state,
+		muted: Cell::new(json["mute"].as_bool().unwrap_or(false)),
+		volumes: RefCell::new(parse_volumes(json)),
+		positions: parse_positions(json),
+	})
+}
+
+/// Parse a sink input or a source output into a session.
+fn parse_stream(json: &Json, kind: Kind) -> Option<Session> {
+	let index = json["index"].as_u64()?;
+	let props = &json["properties"];
+	// Property values are strings, even the numeric ones.
+	let pid = props["application.process.id"]
+		.as_str()
+		.and_then(|s| s.parse::<u32>().ok())
+		.unwrap_or(0);
+	let process = props["application.process.binary"]
+		.as_str()
+		.or_else(|| props["application.name"].as_str())
+		.unwrap_or_default()
+		.to_string();
+
+	let state = if json["corked"].as_bool().unwrap_or(false) {
+		SessionState::Inactive
+	} else {
+		SessionState::Active
+	};
+
+	let control = PulseDevice {
+		kind,
+		index,
+		id: index.to_string(),
+		name: process.clone(),
+		state: DeviceState::ACTIVE,
+		muted: Cell::new(json["mute"].as_bool().unwrap_or(false)),
+		volumes: RefCell::new(parse_volumes(json)),
+		positions: parse_positions(json),
+	};
+
+	Some(Session::new(pid, process, state, control.into()))
+}
+
+fn list(flow: Flow) -> Result<Vec<PulseDevice>> {
+	let json = match flow {
+		Flow::Render => pactl(&["list", "sinks"])?,
+		Flow::Capture => pactl(&["list", "sources"])?,
+	};
+
+	Ok(json
+		.as_array()
+		.map(|a| a.iter().filter_map(|x| parse_device(x, flow)).collect())
+		.unwrap_or_default())
+}
+
+/// Convert a raw volume to a scalar; amplified volumes are above 1.
+fn to_scalar(raw: u32) -> f32 {
+	raw as f32 / VOLUME_NORM
+}
+
+fn to_raw(scalar: f32) -> u32 {
+	(scalar.max(0.0) * VOLUME_NORM).round() as u32
+}
+
+fn to_db(scalar: f32) -> f32 {
+	// PulseAudio uses a cubic mapping between the linear factor and the volume.
+	60.0 * scalar.log10()
+}
+
+fn from_db(db: f32) -> f32 {
+	10f32.powf(db / 60.0)
+}
+
+impl Backend for Pulse {
+	fn default_device(&self, flow: Flow) -> Result<Device> {
+		let (key, kind) = match flow {
+			Flow::Render => ("default_sink_name", "sink"),
+			Flow::Capture => ("default_source_name", "source"),
+		};
+		let info = pactl(&["info"])?;
+		let default = info[key]
+			.as_str()
+			.filter(|s| !s.is_empty())
+			.ok_or_else(|| format!("PulseAudio has no default {kind}"))?;
+
+		list(flow)?
+			.into_iter()
+			.find(|s| s.id == default)
+			.map(Device::from)
+			.ok_or_else(|| format!("the default {kind} {default} was not found").into())
+	}
+
+	fn devices(&self, flow: Flow, state: DeviceState) -> Result<Devices> {
+		let devices = list(flow)?
+			.into_iter()
+			.filter(|s| state.has(s.state))
+			.map(Device::from)
+			.collect::<Vec<_>>();
+
+		Ok(Box::new(devices.into_iter()))
+	}
+
+	/// Follow `pactl subscribe`, whose lines look like `Event 'change' on sink #1`.
+	///
+	/// Server events cover default device changes; stream events matter when adjusting applications.
+	fn subscribe(&self, _flow: Flow, tx: Sender<Result<()>>) -> Result<Subscription> {
+		monitor(
+			"pactl",
+			&["subscribe"],
+			|s| {
+				[" on sink", " on source", " on server", " on card"]
+					.iter()
+					.any(|x| s.contains(x))
+			},
+			tx,
+		)
+	}
+}
+
+impl PulseDevice {
+	fn raw(&self, channel: u32) -> Result<u32> {
+		self.volumes
+			.borrow()
+			.get(channel as usize)
+			.copied()
+			.ok_or_else(|| format!("channel {channel} is out of range").into())
+	}
+
+	fn apply(&self, volumes: Vec<u32>) -> Result<()> {
+		let vals = volumes.iter().map(u32::to_string).collect::<Vec<_>>();
+		let command = format!("set-{}-volume", self.kind.as_str());
+		let mut args = vec![command.as_str(), self.id.as_str()];
+		args.extend(vals.iter().map(String::as_str));
+		exec("pactl", &args)?;
+
+		*self.volumes.borrow_mut() = volumes;
+		Ok(())
+	}
+}
+
+impl Endpoint for PulseDevice {
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn id(&self) -> Result<String> {
+		Ok(self.id.clone())
+	}
+
+	fn state(&self) -> DeviceState {
+		self.state
+	}
+
+	fn sessions(&self) -> Result<Vec<Session>> {
+		let (list, key, kind) = match self.kind {
+			Kind::Sink => ("sink-inputs", "sink", Kind::SinkInput),
+			Kind::Source => ("source-outputs", "source", Kind::SourceOutput),
+			Kind::SinkInput | Kind::SourceOutput => return Ok(Vec::new()),
+		};
+
+		let json = pactl(&["list", list])?;
+		Ok(json
+			.as_array()
+			.map(|a| {
+				a.iter()
+					.filter(|x| x[key].as_u64() == Some(self.index))
+					.filter_map(|x| parse_stream(x, kind))
+					.collect()
+			})
+			.unwrap_or_default())
+	}
+
+	fn set_default(&self) -> Result<()> {
+		match self.kind {
+			Kind::Sink | Kind::Source => {
+				let command = format!("set-default-{}", self.kind.as_str());
+				exec("pactl", &[&command, &self.id])?;
+				Ok(())
+			}
+			Kind::SinkInput | Kind::SourceOutput => {
+				Err("an application cannot be the default device".into())
+			}
+		}
+	}
+
+	fn channels(&self) -> Result<u32> {
+		Ok(self.volumes.borrow().len() as u32)
+	}
+
+	fn channel_map(&self) -> Result<Vec<Option<Position>>> {
+		Ok(self.positions.clone())
+	}
+
+	fn master_volume(&self) -> Result<f32> {
+		let max = self.volumes.borrow().iter().copied().max().unwrap_or(0);
+		Ok(to_scalar(max))
+	}
+
+	fn master_db(&self) -> Result<f32> {
+		self.master_volume().map(to_db)
+	}
+
+	fn set_master_volume(&self, volume: f32) -> Result<()> {
+		let master = self.master_volume()?;
+		let volumes = self
+			.volumes
+			.borrow()
+			.iter()
+			.map(|&raw| {
+				if master == 0.0 {
+					to_raw(volume)
+				} else {
+					to_raw(to_scalar(raw) / master * volume)
+				}
+			})
+			.collect();
+
+		self.apply(volumes)
+	}
+
+	fn is_muted(&self) -> Result<bool> {
+		Ok(self.muted.get())
+	}
+
+	fn set_mute(&self, mute: bool) -> Result<()> {
+		let command = format!("set-{}-mute", self.kind.as_str());
+		exec("pactl", &[&command, &self.id, if mute { "1" } else { "0" }])?;
+		self.muted.set(mute);
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> Result<f32> {
+		self.raw(channel).map(to_scalar)
+	}
+
+	fn channel_db(&self, channel: u32) -> Result<f32> {
+		self.channel_volume(channel).map(to_db)
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
+		self.raw(channel)?;
+		let mut volumes = self.volumes.borrow().clone();
+		volumes[channel as usize] = to_raw(volume);
+		self.apply(volumes)
+	}
+
+	fn set_channel_volumes(&self, volumes: &[f32]) -> Result<()> {
+		if volumes.len() != self.volumes.borrow().len() {
+			return Err("the number of levels does not match the number of channels".into());
+		}
+		self.apply(volumes.iter().map(|&v| to_raw(v)).collect())
+	}
+
+	/// wol doesn't raise levels above 100%, so the range ends at 0dB unless the device is already amplified.
+	fn db_range(&self) -> Result<(f32, f32)> {
+		Ok((f32::NEG_INFINITY, self.master_db()?.max(0.0)))
+	}
+
+	fn db_to_scalar(&self, db: f32) -> f32 {
+		from_db(db)
+	}
+
+	fn scalar_to_db(&self, scalar: f32) -> f32 {
+		to_db(scalar)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::volume::Volume;
+
+	/// A sink as listed by `pactl --format=json list sinks`, trimmed down.
+	fn sink(left: u32, right: u32) -> Json {
+		json!({
+			"index": 52,
+			"state": "RUNNING",
+			"name": "alsa_output.pci-0000_00_1f.3.analog-stereo",
+			"description": "Built-in Audio Analog Stereo",
+			"mute": false,
+			"channel_map": "front-left,front-right",
+			"volume": {
+				"front-left": {"value": left, "value_percent": "", "db": ""},
+				"front-right": {"value": right, "value_percent": "", "db": ""}
+			}
+		})
+	}
+
+	#[test]
+	fn devices() {
+		let dev = parse_device(&sink(32768, 65536), Flow::Render).unwrap();
+		assert_eq!(dev.index, 52);
+		assert_eq!(dev.id, "alsa_output.pci-0000_00_1f.3.analog-stereo");
+		assert_eq!(dev.name, "Built-in Audio Analog Stereo");
+		assert_eq!(dev.state, DeviceState::ACTIVE);
+		assert!(!dev.is_muted().unwrap());
+		assert_eq!(
+			dev.channel_map().unwrap(),
+			[Some(Position::FrontLeft), Some(Position::FrontRight)]
+		);
+		assert_eq!(dev.channel_volume(0).unwrap(), 0.5);
+		assert_eq!(dev.channel_volume(1).unwrap(), 1.0);
+
+		// The volumes follow the channel map, whatever the order of the keys.
+		let mut json = sink(32768, 65536);
+		json["channel_map"] = json!("front-right,front-left,lfe");
+		json["volume"]["lfe"] = json!({"value": 0});
+		json["mute"] = json!(true);
+		let dev = parse_device(&json, Flow::Render).unwrap();
+		assert!(dev.is_muted().unwrap());
+		assert_eq!(*dev.volumes.borrow(), [65536, 32768, 0]);
+		assert_eq!(
+			dev.channel_map().unwrap(),
+			[
+				Some(Position::FrontRight),
+				Some(Position::FrontLeft),
+				Some(Position::Lfe)
+			]
+		);
+
+		let mut json = sink(65536, 65536);
+		json["active_port"] = json!("analog-output-headphones");
+		json["ports"] = json!([
+			{"name": "analog-output-speaker", "availability": "available"},
+			{"name": "analog-output-headphones", "availability": "not available"}
+		]);
+		let dev = parse_device(&json, Flow::Render).unwrap();
+		assert_eq!(dev.state, DeviceState::UNPLUGGED);
+
+		let mut json = sink(65536, 65536);
+		json["monitor_of_sink"] = json!("alsa_output.pci-0000_00_1f.3.analog-stereo");
+		assert!(parse_device(&json, Flow::Capture).is_none());
+		json["monitor_of_sink"] = json!("n/a");
+		assert!(parse_device(&json, Flow::Capture).is_some());
+	}
+
+	#[test]
+	fn streams() {
+		let mut json = sink(65536, 65536);
+		json["corked"] = json!(true);
+		json["properties"] = json!({
+			"application.name": "Firefox",
+			"application.process.id": "4242",
+			"application.process.binary": "firefox"
+		});
+		let session = parse_stream(&json, Kind::SinkInput).unwrap();
+		assert_eq!(session.pid(), 4242);
+		assert_eq!(session.process(), "firefox");
+		assert_eq!(session.state(), SessionState::Inactive);
+		assert_eq!(session.control().id().unwrap(), "52");
+	}
+
+	#[test]
+	fn amplified() {
+		let dev = parse_device(&sink(98304, 65536), Flow::Render).unwrap();
+		assert_eq!(dev.channel_volume(0).unwrap(), 1.5);
+		assert_eq!(to_raw(1.5), 98304);
+
+		// Levels above 100% are not raised, but not lowered by themselves either.
+		let mut vol = Volume::new(dev.into()).unwrap();
+		vol.set_master(vol.master() + 0.1);
+		assert_eq!(vol.channels(), [1.5, 1.0]);
+		vol.set_channel(1, 1.2);
+		assert_eq!(vol.channels(), [1.5, 1.0]);
+		vol.set_master(vol.master() - 0.5);
+		assert_eq!(vol.channels(), [1.0, 1.0 / 1.5]);
+	}
+}
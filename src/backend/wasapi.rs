@@ -0,0 +1,718 @@
+use std::{
+	cell::OnceCell,
+	ffi::c_void,
+	ptr,
+	sync::mpsc::Sender,
+};
+
+use windows::{
+	core::{
+		implement,
+		Interface,
+		Result,
+		HSTRING,
+		PCWSTR,
+		PWSTR,
+	},
+	Win32::{
+		Devices::FunctionDiscovery::*,
+		Foundation::{
+			CloseHandle,
+			S_OK,
+		},
+		Media::Audio::{
+			Endpoints::{
+				IAudioEndpointVolume,
+				IAudioEndpointVolumeCallback,
+				IAudioEndpointVolumeCallback_Impl,
+			},
+			*,
+		},
+		System::{
+			Com::*,
+			Threading::{
+				OpenProcess,
+				QueryFullProcessImageNameW,
+				PROCESS_NAME_WIN32,
+				PROCESS_QUERY_LIMITED_INFORMATION,
+			},
+			Variant::*,
+		},
+		UI::Shell::PropertiesSystem::PROPERTYKEY,
+	},
+};
+
+use self::policy_config::{
+	IPolicyConfig,
+	CLSID_POLICY_CONFIG_CLIENT,
+};
+use super::{
+	Backend,
+	Endpoint,
+	Subscription,
+};
+use crate::{
+	device::{
+		Device,
+		DeviceState,
+		Devices,
+		Flow,
+		Position,
+		Role,
+	},
+	error,
+	session::{
+		Session,
+		SessionState,
+	},
+};
+
+/// `WAVE_FORMAT_EXTENSIBLE`; the format tag of a `WAVEFORMATEXTENSIBLE`.
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// The interface macro doesn't accept attributes, so the lint is silenced for the whole module instead.
+#[allow(non_snake_case)]
+mod policy_config {
+	use std::ffi::c_void;
+
+	use windows::{
+		core::{
+			interface,
+			IUnknown,
+			IUnknown_Vtbl,
+			Result,
+			GUID,
+			PCWSTR,
+		},
+		Win32::Media::Audio::ERole,
+	};
+
+	/// The class ID of the `PolicyConfigClient` coclass.
+	pub const CLSID_POLICY_CONFIG_CLIENT: GUID =
+		GUID::from_u128(0x870af99c_171d_4f9e_af0d_e63df40c2bc9);
+
+	/// The undocumented interface the Sound control panel uses to change the default devices.
+	///
+	/// Only `SetDefaultEndpoint` is used; the other methods are declared to get the vtable layout right.
+	#[interface("f8679f50-850a-41cf-9c72-430f290290c8")]
+	pub unsafe trait IPolicyConfig: IUnknown {
+		fn GetMixFormat(&self, id: PCWSTR, format: *mut c_void) -> Result<()>;
+		fn GetDeviceFormat(&self, id: PCWSTR, default: i32, format: *mut c_void) -> Result<()>;
+		fn ResetDeviceFormat(&self, id: PCWSTR) -> Result<()>;
+		fn SetDeviceFormat(
+			&self,
+			id: PCWSTR,
+			endpoint: *mut c_void,
+			mix: *mut c_void,
+		) -> Result<()>;
+		fn GetProcessingPeriod(
+			&self,
+			id: PCWSTR,
+			default: i32,
+			default_period: *mut c_void,
+			min_period: *mut c_void,
+		) -> Result<()>;
+		fn SetProcessingPeriod(&self, id: PCWSTR, period: *mut c_void) -> Result<()>;
+		fn GetShareMode(&self, id: PCWSTR, mode: *mut c_void) -> Result<()>;
+		fn SetShareMode(&self, id: PCWSTR, mode: *mut c_void) -> Result<()>;
+		fn GetPropertyValue(
+			&self,
+			id: PCWSTR,
+			key: *const c_void,
+			value: *mut c_void,
+		) -> Result<()>;
+		fn SetPropertyValue(
+			&self,
+			id: PCWSTR,
+			key: *const c_void,
+			value: *mut c_void,
+		) -> Result<()>;
+		pub fn SetDefaultEndpoint(&self, id: PCWSTR, role: ERole) -> Result<()>;
+		fn SetEndpointVisibility(&self, id: PCWSTR, visible: i32) -> Result<()>;
+	}
+}
+
+pub struct Wasapi;
+
+struct Endpoints {
+	len: u32,
+	cur: u32,
+	collection: IMMDeviceCollection,
+}
+
+struct WasapiDevice {
+	name: String,
+	dev: IMMDevice,
+	vol: OnceCell<IAudioEndpointVolume>,
+	state: DeviceState,
+}
+
+/// The audio session of an application.
+///
+/// A session has a volume and a volume per channel which multiply; the levels exposed here are the products.
+struct WasapiSession {
+	process: String,
+	pid: u32,
+	simple: ISimpleAudioVolume,
+	channels: IChannelAudioVolume,
+}
+
+/// Forwards default device changes and device state changes.
+#[implement(IMMNotificationClient)]
+struct DeviceNotifications {
+	flow: EDataFlow,
+	tx: Sender<error::Result<()>>,
+}
+
+/// Forwards level and mute changes of an endpoint.
+#[implement(IAudioEndpointVolumeCallback)]
+struct VolumeNotifications(Sender<error::Result<()>>);
+
+/// Unregisters a [DeviceNotifications] client when dropped.
+struct DeviceSubscription {
+	enumerator: IMMDeviceEnumerator,
+	client: IMMNotificationClient,
+}
+
+/// Unregisters a [VolumeNotifications] callback when dropped.
+struct VolumeSubscription {
+	vol: IAudioEndpointVolume,
+	callback: IAudioEndpointVolumeCallback,
+}
+
+impl IMMNotificationClient_Impl for DeviceNotifications_Impl {
+	fn OnDeviceStateChanged(&self, _id: &PCWSTR, _state: DEVICE_STATE) -> Result<()> {
+		let _ = self.tx.send(Ok(()));
+		Ok(())
+	}
+
+	fn OnDeviceAdded(&self, _id: &PCWSTR) -> Result<()> {
+		Ok(())
+	}
+
+	fn OnDeviceRemoved(&self, _id: &PCWSTR) -> Result<()> {
+		Ok(())
+	}
+
+	fn OnDefaultDeviceChanged(&self, flow: EDataFlow, _role: ERole, _id: &PCWSTR) -> Result<()> {
+		if flow == self.flow {
+			let _ = self.tx.send(Ok(()));
+		}
+		Ok(())
+	}
+
+	fn OnPropertyValueChanged(&self, _id: &PCWSTR, _key: &PROPERTYKEY) -> Result<()> {
+		Ok(())
+	}
+}
+
+impl IAudioEndpointVolumeCallback_Impl for VolumeNotifications_Impl {
+	fn OnNotify(&self, _data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<()> {
+		let _ = self.0.send(Ok(()));
+		Ok(())
+	}
+}
+
+impl Drop for DeviceSubscription {
+	fn drop(&mut self) {
+		unsafe {
+			let _ = self
+				.enumerator
+				.UnregisterEndpointNotificationCallback(&self.client);
+		}
+	}
+}
+
+impl Drop for VolumeSubscription {
+	fn drop(&mut self) {
+		unsafe {
+			let _ = self.vol.UnregisterControlChangeNotify(&self.callback);
+		}
+	}
+}
+
+impl Iterator for Endpoints {
+	type Item = Device;
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let n = u32::saturating_sub(self.len, self.cur) as usize;
+		(n, Some(n))
+	}
+
+	fn count(self) -> usize {
+		u32::saturating_sub(self.len, self.cur) as usize
+	}
+
+	fn nth(&mut self, n: usize) -> Option<Self::Item> {
+		if n >= self.len as usize {
+			return None;
+		}
+
+		self.cur = n as u32;
+		self.next()
+	}
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.cur >= self.len {
+			None
+		} else {
+			let x = unsafe {
+				self.collection
+					.Item(self.cur)
+					.and_then(|dev| WasapiDevice::new(dev))
+					.ok()
+			};
+			self.cur += 1;
+			x.map(Device::from)
+		}
+	}
+}
+
+unsafe fn enumerator() -> Result<IMMDeviceEnumerator> {
+	CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
+	let mm_enum: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+
+	Ok(mm_enum)
+}
+
+fn data_flow(flow: Flow) -> EDataFlow {
+	match flow {
+		Flow::Render => eRender,
+		Flow::Capture => eCapture,
+	}
+}
+
+fn role(role: Role) -> ERole {
+	match role {
+		Role::Console => eConsole,
+		Role::Multimedia => eMultimedia,
+		Role::Communications => eCommunications,
+	}
+}
+
+unsafe fn endpoint_id(dev: &IMMDevice) -> Result<String> {
+	let id = dev.GetId()?;
+	if id.is_null() {
+		return Ok(String::new());
+	}
+	let s = String::from_utf16_lossy(id.as_wide());
+	CoTaskMemFree(Some(id.0 as *const _));
+	Ok(s)
+}
+
+/// Get the executable name of a process, e.g. `firefox.exe`.
+unsafe fn process_name(pid: u32) -> Option<String> {
+	let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+	let mut buf = [0_u16; 1024];
+	let mut len = buf.len() as u32;
+	let res = QueryFullProcessImageNameW(
+		handle,
+		PROCESS_NAME_WIN32,
+		PWSTR(buf.as_mut_ptr()),
+		&mut len,
+	);
+	let _ = CloseHandle(handle);
+	res.ok()?;
+
+	let path = String::from_utf16_lossy(&buf[..len as usize]);
+	path.rsplit('\\').next().map(String::from)
+}
+
+impl Backend for Wasapi {
+	fn default_device(&self, flow: Flow) -> error::Result<Device> {
+		unsafe {
+			let mm_enum = enumerator()?;
+			let dev = mm_enum.GetDefaultAudioEndpoint(data_flow(flow), eConsole)?;
+			Ok(WasapiDevice::new(dev)?.into())
+		}
+	}
+
+	fn subscribe(&self, flow: Flow, tx: Sender<error::Result<()>>) -> error::Result<Subscription> {
+		unsafe {
+			let enumerator = enumerator()?;
+			let client: IMMNotificationClient = DeviceNotifications {
+				flow: data_flow(flow),
+				tx,
+			}
+			.into();
+			enumerator.RegisterEndpointNotificationCallback(&client)?;
+
+			Ok(Box::new(DeviceSubscription { enumerator, client }))
+		}
+	}
+
+	fn default_id(&self, flow: Flow, r: Role) -> error::Result<String> {
+		unsafe {
+			let mm_enum = enumerator()?;
+			let dev = mm_enum.GetDefaultAudioEndpoint(data_flow(flow), role(r))?;
+			Ok(endpoint_id(&dev)?)
+		}
+	}
+
+	fn devices(&self, flow: Flow, state: DeviceState) -> error::Result<Devices> {
+		unsafe {
+			let enumerator = enumerator()?;
+			let enumerator =
+				enumerator.EnumAudioEndpoints(data_flow(flow), DEVICE_STATE(state.0))?;
+
+			Ok(Box::new(Endpoints {
+				cur: 0,
+				len: enumerator.GetCount()?,
+				collection: enumerator,
+			}))
+		}
+	}
+}
+
+impl WasapiDevice {
+	unsafe fn new(dev: IMMDevice) -> Result<Self> {
+		let state = DeviceState(dev.GetState()?.0);
+		let vol = OnceCell::new();
+		let props = dev.OpenPropertyStore(STGM_READ)?;
+		let varname = props
+			.GetValue(&PKEY_Device_FriendlyName)?
+			.as_raw()
+			.Anonymous;
+		if varname.Anonymous.vt == VT_EMPTY.0 {
+			return Ok(Self {
+				dev,
+				vol,
+				state,
+				name: String::new(),
+			});
+		}
+
+		let name = PWSTR(varname.Anonymous.Anonymous.pwszVal);
+		if name.is_null() {
+			Ok(Self {
+				dev,
+				vol,
+				state,
+				name: String::new(),
+			})
+		} else {
+			Ok(Self {
+				dev,
+				vol,
+				state,
+				name: String::from_utf16_lossy(name.as_wide()),
+			})
+		}
+	}
+
+	#[inline]
+	unsafe fn vol(&self) -> Result<&IAudioEndpointVolume> {
+		if let Some(x) = self.vol.get() {
+			return Ok(x);
+		}
+		let vol = self.dev.Activate(CLSCTX_ALL, None)?;
+		Ok(self.vol.get_or_init(move || vol))
+	}
+
+	/// Get the description of this device.
+	///
+	/// Reads the [PKEY_Device_DeviceDesc](https://learn.microsoft.com/en-us/windows/win32/coreaudio/pkey-device-devicedesc) property.
+	fn _description(&self) -> Result<String> {
+		unsafe {
+			let props = self.dev.OpenPropertyStore(STGM_READ)?;
+			let varname = props.GetValue(&PKEY_Device_DeviceDesc)?.as_raw().Anonymous;
+			if varname.Anonymous.vt == VT_EMPTY.0 {
+				return Ok(String::new());
+			}
+
+			let desc = PWSTR(varname.Anonymous.Anonymous.pwszVal);
+			if desc.is_null() {
+				Ok(String::new())
+			} else {
+				Ok(String::from_utf16_lossy(desc.as_wide()))
+			}
+		}
+	}
+}
+
+impl Endpoint for WasapiDevice {
+	/// Get the friendly name of this device.
+	///
+	/// Reads the [PKEY_Device_FriendlyName](https://learn.microsoft.com/en-us/windows/win32/coreaudio/pkey-device-friendlyname) property.
+	fn name(&self) -> &str {
+		&self.name
+	}
+
+	fn id(&self) -> error::Result<String> {
+		Ok(unsafe { endpoint_id(&self.dev)? })
+	}
+
+	fn state(&self) -> DeviceState {
+		self.state
+	}
+
+	fn subscribe(&self, tx: Sender<error::Result<()>>) -> error::Result<Subscription> {
+		unsafe {
+			let vol = self.vol()?.clone();
+			let callback: IAudioEndpointVolumeCallback = VolumeNotifications(tx).into();
+			vol.RegisterControlChangeNotify(&callback)?;
+
+			Ok(Box::new(VolumeSubscription { vol, callback }))
+		}
+	}
+
+	fn sessions(&self) -> error::Result<Vec<Session>> {
+		unsafe {
+			let manager: IAudioSessionManager2 = self.dev.Activate(CLSCTX_ALL, None)?;
+			let sessions = manager.GetSessionEnumerator()?;
+			let mut out = Vec::new();
+
+			for i in 0..sessions.GetCount()? {
+				let control: IAudioSessionControl2 = sessions.GetSession(i)?.cast()?;
+				let state = control.GetState()?;
+				let state = if state == AudioSessionStateActive {
+					SessionState::Active
+				} else if state == AudioSessionStateInactive {
+					SessionState::Inactive
+				} else {
+					SessionState::Expired
+				};
+				// Sessions that span multiple processes have no single process ID.
+				let pid = control.GetProcessId().unwrap_or(0);
+				let process = if control.IsSystemSoundsSession() == S_OK {
+					String::from("System Sounds")
+				} else {
+					process_name(pid).unwrap_or_default()
+				};
+
+				let session = WasapiSession {
+					process: process.clone(),
+					pid,
+					simple: control.cast()?,
+					channels: control.cast()?,
+				};
+				out.push(Session::new(pid, process, state, session.into()));
+			}
+
+			Ok(out)
+		}
+	}
+
+	fn set_default(&self) -> error::Result<()> {
+		unsafe {
+			let id = HSTRING::from(endpoint_id(&self.dev)?);
+			let policy: IPolicyConfig =
+				CoCreateInstance(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)?;
+			for r in Role::ALL {
+				policy.SetDefaultEndpoint(PCWSTR(id.as_ptr()), role(r))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn channels(&self) -> error::Result<u32> {
+		Ok(unsafe { self.vol()?.GetChannelCount()? })
+	}
+
+	/// Read the speaker mask of the mix format; devices without one are assumed to have the usual layouts.
+	fn channel_map(&self) -> error::Result<Vec<Option<Position>>> {
+		let channels = self.channels()?;
+		let mask = unsafe {
+			let client: IAudioClient = self.dev.Activate(CLSCTX_ALL, None)?;
+			let format = client.GetMixFormat()?;
+			let mask = if (*format).wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+				(*format.cast::<WAVEFORMATEXTENSIBLE>()).dwChannelMask
+			} else {
+				0
+			};
+			CoTaskMemFree(Some(format as *const c_void));
+			mask
+		};
+
+		if mask == 0 {
+			return Ok(Position::default_layout(channels));
+		}
+
+		// The channels are in the order of the bits that are set.
+		let mut positions = (0..32)
+			.filter(|bit| mask & (1 << bit) != 0)
+			.map(|bit| Position::MASK_ORDER.get(bit).copied())
+			.collect::<Vec<_>>();
+		positions.resize(channels as usize, None);
+		Ok(positions)
+	}
+
+	fn master_volume(&self) -> error::Result<f32> {
+		Ok(unsafe { self.vol()?.GetMasterVolumeLevelScalar()? })
+	}
+
+	fn master_db(&self) -> error::Result<f32> {
+		Ok(unsafe { self.vol()?.GetMasterVolumeLevel()? })
+	}
+
+	fn set_master_volume(&self, volume: f32) -> error::Result<()> {
+		unsafe {
+			self.vol()?
+				.SetMasterVolumeLevelScalar(volume, ptr::null())?
+		};
+		Ok(())
+	}
+
+	fn is_muted(&self) -> error::Result<bool> {
+		Ok(unsafe { self.vol()?.GetMute()?.as_bool() })
+	}
+
+	fn set_mute(&self, mute: bool) -> error::Result<()> {
+		unsafe { self.vol()?.SetMute(mute, ptr::null())? };
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> error::Result<f32> {
+		Ok(unsafe { self.vol()?.GetChannelVolumeLevelScalar(channel)? })
+	}
+
+	fn channel_db(&self, channel: u32) -> error::Result<f32> {
+		Ok(unsafe { self.vol()?.GetChannelVolumeLevel(channel)? })
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> error::Result<()> {
+		unsafe {
+			self.vol()?
+				.SetChannelVolumeLevelScalar(channel, volume, ptr::null())?
+		};
+		Ok(())
+	}
+
+	fn db_range(&self) -> error::Result<(f32, f32)> {
+		let (mut min, mut max, mut step) = (0.0, 0.0, 0.0);
+		unsafe { self.vol()?.GetVolumeRange(&mut min, &mut max, &mut step)? };
+		Ok((min, max))
+	}
+
+	fn set_master_db(&self, db: f32) -> error::Result<()> {
+		unsafe { self.vol()?.SetMasterVolumeLevel(db, ptr::null())? };
+		Ok(())
+	}
+
+	fn set_channel_db(&self, channel: u32, db: f32) -> error::Result<()> {
+		unsafe {
+			self.vol()?
+				.SetChannelVolumeLevel(channel, db, ptr::null())?
+		};
+		Ok(())
+	}
+}
+
+impl WasapiSession {
+	unsafe fn levels(&self) -> Result<Vec<f32>> {
+		let volume = self.simple.GetMasterVolume()?;
+		(0..self.channels.GetChannelCount()?)
+			.map(|i| Ok(volume * self.channels.GetChannelVolume(i)?))
+			.collect()
+	}
+
+	/// Set the session volume to the loudest level and the channel volumes relative to it.
+	unsafe fn apply(&self, levels: &[f32]) -> Result<()> {
+		let volume = levels
+			.iter()
+			.copied()
+			.max_by(f32::total_cmp)
+			.unwrap_or(0.0)
+			.clamp(0.0, 1.0);
+		self.simple.SetMasterVolume(volume, ptr::null())?;
+
+		if volume > 0.0 {
+			for (i, &level) in levels.iter().enumerate() {
+				self.channels.SetChannelVolume(
+					i as u32,
+					(level / volume).clamp(0.0, 1.0),
+					ptr::null(),
+				)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Endpoint for WasapiSession {
+	fn name(&self) -> &str {
+		&self.process
+	}
+
+	fn id(&self) -> error::Result<String> {
+		Ok(self.pid.to_string())
+	}
+
+	fn state(&self) -> DeviceState {
+		DeviceState::ACTIVE
+	}
+
+	fn channels(&self) -> error::Result<u32> {
+		Ok(unsafe { self.channels.GetChannelCount()? })
+	}
+
+	fn master_volume(&self) -> error::Result<f32> {
+		let levels = unsafe { self.levels()? };
+		match levels.into_iter().max_by(f32::total_cmp) {
+			Some(x) => Ok(x),
+			None => Ok(unsafe { self.simple.GetMasterVolume()? }),
+		}
+	}
+
+	fn master_db(&self) -> error::Result<f32> {
+		Err("application sessions do not report decibels".into())
+	}
+
+	fn set_master_volume(&self, volume: f32) -> error::Result<()> {
+		unsafe {
+			let mut levels = self.levels()?;
+			let master = levels.iter().copied().max_by(f32::total_cmp).unwrap_or(0.0);
+			for level in &mut levels {
+				*level = if master == 0.0 {
+					volume
+				} else {
+					*level / master * volume
+				};
+			}
+			self.apply(&levels)?;
+		}
+		Ok(())
+	}
+
+	fn is_muted(&self) -> error::Result<bool> {
+		Ok(unsafe { self.simple.GetMute()?.as_bool() })
+	}
+
+	fn set_mute(&self, mute: bool) -> error::Result<()> {
+		unsafe { self.simple.SetMute(mute, ptr::null())? };
+		Ok(())
+	}
+
+	fn channel_volume(&self, channel: u32) -> error::Result<f32> {
+		unsafe { self.levels()? }
+			.get(channel as usize)
+			.copied()
+			.ok_or_else(|| format!("channel {channel} is out of range").into())
+	}
+
+	fn channel_db(&self, _channel: u32) -> error::Result<f32> {
+		Err("application sessions do not report decibels".into())
+	}
+
+	fn db_range(&self) -> error::Result<(f32, f32)> {
+		Err("application sessions do not support decibel levels".into())
+	}
+
+	fn set_channel_volume(&self, channel: u32, volume: f32) -> error::Result<()> {
+		unsafe {
+			let mut levels = self.levels()?;
+			let level = levels
+				.get_mut(channel as usize)
+				.ok_or_else(|| format!("channel {channel} is out of range"))?;
+			*level = volume;
+			self.apply(&levels)?;
+		}
+		Ok(())
+	}
+
+	fn set_channel_volumes(&self, volumes: &[f32]) -> error::Result<()> {
+		unsafe { self.apply(volumes)? };
+		Ok(())
+	}
+}
@@ -0,0 +1,320 @@
+use std::{
+	env,
+	fs,
+	io::ErrorKind,
+	path::PathBuf,
+};
+
+use toml::{
+	Table,
+	Value,
+};
+
+use crate::{
+	device::Device,
+	dirs,
+	error::Result,
+	volume::{
+		Floor,
+		FloorPolicy,
+		Limit,
+		LimitAction,
+	},
+};
+
+/// A device selected by an alias, e.g. `hp = { id = "..." }`.
+pub enum Alias {
+	Id(String),
+	Name(String),
+}
+
+/// The settings of the configuration file; command line flags and environment variables take precedence over these.
+#[derive(Default)]
+pub struct Config {
+	/// The device used unless --device or --id is given; may be an alias.
+	pub device: Option<String>,
+	pub id: Option<String>,
+	pub backend: Option<String>,
+	pub quiet: bool,
+	pub force: bool,
+	pub format: Option<String>,
+	pub aliases: Vec<(String, Alias)>,
+	/// The maximum level of every device without its own.
+	pub max: Option<Limit>,
+	/// The maximum levels of devices given by alias, ID or name.
+	pub device_max: Vec<(String, Limit)>,
+	pub max_action: LimitAction,
+	/// The level of the floor as a scalar.
+	pub floor: Option<f32>,
+	pub floor_policy: Option<FloorPolicy>,
+	pub confirm: bool,
+}
+
+impl Config {
+	/// Read the configuration file from `path`, `$WOL_CONFIG` or the default location, in that order.
+	///
+	/// A missing file at the default location is an empty configuration.
+	pub fn load(path: Option<&str>) -> Result<Self> {
+		let explicit = path
+			.map(String::from)
+			.or_else(|| env::var("WOL_CONFIG").ok().filter(|s| !s.is_empty()));
+		let path = match &explicit {
+			Some(p) => PathBuf::from(p),
+			// Without a home directory there is no default file to read either.
+			None => match dirs::config_dir() {
+				Ok(dir) => dir.join("config.toml"),
+				Err(_) => return Ok(Self::default()),
+			},
+		};
+
+		let s = match fs::read_to_string(&path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == ErrorKind::NotFound && explicit.is_none() => {
+				return Ok(Self::default());
+			}
+			Err(e) => return Err(format!("failed to read {}: {e}", path.display()).into()),
+		};
+		let table = s
+			.parse::<Table>()
+			.map_err(|e| format!("failed to parse {}: {e}", path.display()))?;
+
+		Self::from_table(table).map_err(|e| format!("{}: {e}", path.display()).into())
+	}
+
+	/// Parse the contents of a configuration file.
+	#[cfg(test)]
+	pub fn parse(s: &str) -> Result<Self, String> {
+		Self::from_table(s.parse::<Table>().map_err(|e| e.to_string())?)
+	}
+
+	fn from_table(table: Table) -> Result<Self, String> {
+		let mut x = Self::default();
+		for (key, val) in table {
+			match key.as_str() {
+				"device" => x.device = Some(string(&key, val)?),
+				"id" => x.id = Some(string(&key, val)?),
+				"backend" => x.backend = Some(string(&key, val)?),
+				"quiet" => x.quiet = boolean(&key, val)?,
+				"force" => x.force = boolean(&key, val)?,
+				"format" => x.format = Some(string(&key, val)?),
+				"max" => x.max = Some(limit(&key, val)?),
+				"device_max" => {
+					let Value::Table(t) = val else {
+						return Err(String::from("'device_max' must be a table"));
+					};
+					for (dev, val) in t {
+						x.device_max.push((dev, limit(&key, val)?));
+					}
+				}
+				"floor" => {
+					let s = match val {
+						Value::Integer(n) => n.to_string(),
+						Value::Float(n) => n.to_string(),
+						Value::String(s) => s,
+						_ => return Err(String::from("'floor' must be a number")),
+					};
+					x.floor = Some(Floor::parse_level(&s).map_err(String::from)?);
+				}
+				"floor_policy" => {
+					x.floor_policy =
+						Some(FloorPolicy::parse(&string(&key, val)?).ok_or_else(|| {
+							String::from("'floor_policy' must be 'screen-reader' or 'always'")
+						})?);
+				}
+				"confirm" => x.confirm = boolean(&key, val)?,
+				"max_action" => {
+					x.max_action = match string(&key, val)?.as_str() {
+						"clamp" => LimitAction::Clamp,
+						"refuse" => LimitAction::Refuse,
+						_ => return Err(String::from("'max_action' must be 'clamp' or 'refuse'")),
+					}
+				}
+				"aliases" => {
+					let Value::Table(aliases) = val else {
+						return Err(String::from("'aliases' must be a table"));
+					};
+					for (name, val) in aliases {
+						let entry = match val {
+							Value::Table(t) if t.len() == 1 => t.into_iter().next(),
+							_ => None,
+						};
+						let alias = match entry {
+							Some((k, Value::String(s))) if k == "id" => Alias::Id(s),
+							Some((k, Value::String(s))) if k == "name" => Alias::Name(s),
+							_ => return Err(format!("alias '{name}' must be either {{ id = \"...\" }} or {{ name = \"...\" }}")),
+						};
+						x.aliases.push((name, alias));
+					}
+				}
+				_ => return Err(format!("unknown key '{key}'")),
+			}
+		}
+
+		Ok(x)
+	}
+
+	/// Get the maximum level of a device.
+	///
+	/// The keys of the per-device maximums are tried as aliases, then as IDs and then as case-insensitive substrings of the name.
+	pub fn max_for(&self, dev: &Device) -> Option<Limit> {
+		let id = dev.id().unwrap_or_default();
+		let name = dev.name().to_uppercase();
+		let has_name = |s: &str| name.contains(&s.to_uppercase());
+
+		self.device_max
+			.iter()
+			.find(|(key, _)| match self.alias(key) {
+				Some(Alias::Id(s)) => id.eq_ignore_ascii_case(s),
+				Some(Alias::Name(s)) => has_name(s),
+				None => id.eq_ignore_ascii_case(key) || has_name(key),
+			})
+			.map(|&(_, limit)| limit)
+			.or(self.max)
+	}
+
+	/// Find an alias by its name, ignoring the case.
+	pub fn alias(&self, name: &str) -> Option<&Alias> {
+		self.aliases
+			.iter()
+			.find(|(s, _)| s.eq_ignore_ascii_case(name))
+			.map(|(_, alias)| alias)
+	}
+}
+
+fn string(key: &str, val: Value) -> Result<String, String> {
+	match val {
+		Value::String(s) => Ok(s),
+		_ => Err(format!("'{key}' must be a string")),
+	}
+}
+
+/// A maximum level: a percentage as a number, or a string such as `"80%"` or `"-10db"`.
+fn limit(key: &str, val: Value) -> Result<Limit, String> {
+	match val {
+		Value::Integer(n) => Limit::parse(&n.to_string()),
+		Value::Float(n) => Limit::parse(&n.to_string()),
+		Value::String(s) => Limit::parse(&s),
+		_ => return Err(format!("'{key}' must be a number or a string")),
+	}
+	.map_err(|e| format!("invalid value for '{key}': {e}"))
+}
+
+fn boolean(key: &str, val: Value) -> Result<bool, String> {
+	match val {
+		Value::Boolean(b) => Ok(b),
+		_ => Err(format!("'{key}' must be true or false")),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		backend::{
+			memory::Memory,
+			Backend,
+		},
+		device::{
+			DeviceState,
+			Flow,
+		},
+	};
+
+	#[test]
+	fn keys() {
+		let config = Config::parse(
+			r#"
+device = "hp"
+backend = "pulse"
+quiet = true
+format = "json"
+max = 80
+max_action = "refuse"
+floor = 10
+floor_policy = "always"
+confirm = true
+
+[aliases]
+hp = { id = "memory:1" }
+tv = { name = "HDMI" }
+
+[device_max]
+hp = "-12db"
+"#,
+		)
+		.unwrap();
+
+		assert_eq!(config.device.as_deref(), Some("hp"));
+		assert_eq!(config.backend.as_deref(), Some("pulse"));
+		assert!(config.quiet && !config.force && config.confirm);
+		assert_eq!(config.format.as_deref(), Some("json"));
+		assert_eq!(config.max, Some(Limit::Scalar(0.8)));
+		assert_eq!(config.max_action, LimitAction::Refuse);
+		assert_eq!(config.floor, Some(0.1));
+		assert_eq!(config.floor_policy, Some(FloorPolicy::Always));
+		assert!(matches!(config.alias("HP"), Some(Alias::Id(s)) if s == "memory:1"));
+		assert!(matches!(config.alias("tv"), Some(Alias::Name(s)) if s == "HDMI"));
+		assert!(config.alias("speakers").is_none());
+		assert_eq!(config.device_max, [(String::from("hp"), Limit::Db(-12.0))]);
+	}
+
+	#[test]
+	fn errors() {
+		let err = |s| Config::parse(s).err().unwrap();
+		assert_eq!(err("volume = 50"), "unknown key 'volume'");
+		assert_eq!(err("quiet = 1"), "'quiet' must be true or false");
+		assert_eq!(err("device = 1"), "'device' must be a string");
+		assert_eq!(err("max = true"), "'max' must be a number or a string");
+		assert!(err("max = 120").starts_with("invalid value for 'max': "));
+		assert!(err("[device_max]\nhp = \"loud\"").starts_with("invalid value for 'device_max': "));
+		assert_eq!(
+			err("max_action = \"lower\""),
+			"'max_action' must be 'clamp' or 'refuse'"
+		);
+		assert_eq!(
+			err("floor_policy = \"never\""),
+			"'floor_policy' must be 'screen-reader' or 'always'"
+		);
+		assert_eq!(
+			err("[aliases]\nhp = \"memory:1\""),
+			"alias 'hp' must be either { id = \"...\" } or { name = \"...\" }"
+		);
+		assert_eq!(
+			err("[aliases]\nhp = { id = \"a\", name = \"b\" }"),
+			"alias 'hp' must be either { id = \"...\" } or { name = \"...\" }"
+		);
+		assert!(Config::parse("device = ").is_err());
+	}
+
+	#[test]
+	fn max_for() {
+		let config = Config::parse(
+			r#"
+max = 80
+
+[aliases]
+hp = { id = "MEMORY:1" }
+
+[device_max]
+hp = 30
+"memory:2" = 40
+hdmi = 50
+"#,
+		)
+		.unwrap();
+		let backend = Memory::new("Speakers=50;Headset=50;Monitor=50;LG HDMI=50").unwrap();
+		let max = |n: usize| {
+			let dev = backend
+				.devices(Flow::Render, DeviceState::ANY)
+				.unwrap()
+				.nth(n)
+				.unwrap();
+			config.max_for(&dev)
+		};
+
+		assert_eq!(max(0), Some(Limit::Scalar(0.8)));
+		assert_eq!(max(1), Some(Limit::Scalar(0.3)));
+		assert_eq!(max(2), Some(Limit::Scalar(0.4)));
+		assert_eq!(max(3), Some(Limit::Scalar(0.5)));
+	}
+}
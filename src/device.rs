@@ -1,7 +1,6 @@
 use std::{
-	cell::OnceCell,
 	fmt,
-	ptr,
+	sync::mpsc::Sender,
 };
 
 use derive_more::derive::{
@@ -12,37 +11,21 @@ use derive_more::derive::{
 	From,
 	Into,
 };
-use windows::{
-	core::{
-		Result,
-		PWSTR,
-	},
-	Win32::{
-		Devices::FunctionDiscovery::*,
-		Media::Audio::{
-			Endpoints::IAudioEndpointVolume,
-			*,
-		},
-		System::{
-			Com::*,
-			Variant::*,
-		},
+
+use crate::{
+	backend::{
+		Backend,
+		Endpoint,
+		Subscription,
 	},
+	error::Result,
+	session::Session,
 };
 
-#[derive(Debug)]
-pub struct Devices {
-	len: u32,
-	cur: u32,
-	collection: IMMDeviceCollection,
-}
+pub type Devices = Box<dyn Iterator<Item = Device>>;
 
-#[derive(Debug)]
 pub struct Device {
-	name: String,
-	dev: IMMDevice,
-	vol: OnceCell<IAudioEndpointVolume>,
-	state: DeviceState,
+	inner: Box<dyn Endpoint>,
 }
 
 #[derive(
@@ -64,18 +47,165 @@ pub struct Device {
 pub struct DeviceState(pub u32);
 
 impl DeviceState {
-	pub const ACTIVE: Self = Self(DEVICE_STATE_ACTIVE.0);
+	// The values match the DEVICE_STATE_* constants of the Windows Core Audio API.
+	pub const ACTIVE: Self = Self(0x1);
 	pub const ANY: Self =
 		Self(Self::ACTIVE.0 | Self::DISABLED.0 | Self::NOT_PRESENT.0 | Self::UNPLUGGED.0);
-	pub const DISABLED: Self = Self(DEVICE_STATE_DISABLED.0);
-	pub const NOT_PRESENT: Self = Self(DEVICE_STATE_NOTPRESENT.0);
-	pub const UNPLUGGED: Self = Self(DEVICE_STATE_UNPLUGGED.0);
+	pub const DISABLED: Self = Self(0x2);
+	pub const NOT_PRESENT: Self = Self(0x4);
+	pub const UNPLUGGED: Self = Self(0x8);
 
 	pub const fn has(self, flag: Self) -> bool {
 		self.0 | flag.0 == self.0
 	}
 }
 
+/// The direction of an audio endpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Flow {
+	/// Output devices, such as speakers.
+	Render,
+	/// Input devices, such as microphones.
+	Capture,
+}
+
+impl fmt::Display for Flow {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Render => "output",
+			Self::Capture => "input",
+		})
+	}
+}
+
+/// The role a default device is assigned for.
+///
+/// Only WASAPI distinguishes between the roles; the other backends have a single default device per flow.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Role {
+	/// Games, system sounds and voice commands.
+	Console,
+	/// Music and video playback.
+	Multimedia,
+	/// Voice chat and calls.
+	Communications,
+}
+
+impl Role {
+	pub const ALL: [Self; 3] = [Self::Console, Self::Multimedia, Self::Communications];
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Console => "console",
+			Self::Multimedia => "multimedia",
+			Self::Communications => "communications",
+		})
+	}
+}
+
+/// The speaker position of a channel.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Position {
+	Mono,
+	FrontLeft,
+	FrontRight,
+	FrontCenter,
+	Lfe,
+	BackLeft,
+	BackRight,
+	FrontLeftCenter,
+	FrontRightCenter,
+	BackCenter,
+	SideLeft,
+	SideRight,
+}
+
+impl Position {
+	/// The positions in the order of the bits of a Windows speaker mask (`SPEAKER_FRONT_LEFT` and so on).
+	#[cfg_attr(not(windows), allow(dead_code))]
+	pub const MASK_ORDER: [Self; 11] = [
+		Self::FrontLeft,
+		Self::FrontRight,
+		Self::FrontCenter,
+		Self::Lfe,
+		Self::BackLeft,
+		Self::BackRight,
+		Self::FrontLeftCenter,
+		Self::FrontRightCenter,
+		Self::BackCenter,
+		Self::SideLeft,
+		Self::SideRight,
+	];
+
+	/// Parse a short name such as `FL` or `LFE`, case-insensitively.
+	///
+	/// The PipeWire names, e.g. `RL` for the back left channel, are accepted as well.
+	pub fn parse(s: &str) -> Option<Self> {
+		Some(match s.to_ascii_uppercase().as_str() {
+			"MONO" => Self::Mono,
+			"FL" => Self::FrontLeft,
+			"FR" => Self::FrontRight,
+			"C" | "FC" => Self::FrontCenter,
+			"LFE" => Self::Lfe,
+			"BL" | "RL" => Self::BackLeft,
+			"BR" | "RR" => Self::BackRight,
+			"FLC" => Self::FrontLeftCenter,
+			"FRC" => Self::FrontRightCenter,
+			"BC" | "RC" => Self::BackCenter,
+			"SL" => Self::SideLeft,
+			"SR" => Self::SideRight,
+			_ => return None,
+		})
+	}
+
+	/// The positions of devices that don't report them, going by the usual layouts for the number of channels.
+	pub fn default_layout(channels: u32) -> Vec<Option<Self>> {
+		use Position::*;
+		let layout: &[Self] = match channels {
+			1 => &[Mono],
+			2 => &[FrontLeft, FrontRight],
+			4 => &[FrontLeft, FrontRight, BackLeft, BackRight],
+			6 => &[FrontLeft, FrontRight, FrontCenter, Lfe, BackLeft, BackRight],
+			8 => &[
+				FrontLeft,
+				FrontRight,
+				FrontCenter,
+				Lfe,
+				BackLeft,
+				BackRight,
+				SideLeft,
+				SideRight,
+			],
+			_ => &[],
+		};
+
+		(0..channels as usize)
+			.map(|i| layout.get(i).copied())
+			.collect()
+	}
+}
+
+impl fmt::Display for Position {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Mono => "MONO",
+			Self::FrontLeft => "FL",
+			Self::FrontRight => "FR",
+			Self::FrontCenter => "C",
+			Self::Lfe => "LFE",
+			Self::BackLeft => "BL",
+			Self::BackRight => "BR",
+			Self::FrontLeftCenter => "FLC",
+			Self::FrontRightCenter => "FRC",
+			Self::BackCenter => "BC",
+			Self::SideLeft => "SL",
+			Self::SideRight => "SR",
+		})
+	}
+}
+
 impl fmt::Display for DeviceState {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let x = *self;
@@ -96,181 +226,136 @@ impl fmt::Display for DeviceState {
 	}
 }
 
-impl Iterator for Devices {
-	type Item = Device;
-
-	fn size_hint(&self) -> (usize, Option<usize>) {
-		let n = u32::saturating_sub(self.len, self.cur) as usize;
-		(n, Some(n))
+impl fmt::Debug for Device {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Device")
+			.field("name", &self.name())
+			.field("state", &self.state())
+			.finish_non_exhaustive()
 	}
+}
 
-	fn count(self) -> usize {
-		u32::saturating_sub(self.len, self.cur) as usize
+impl<E: Endpoint + 'static> From<E> for Device {
+	fn from(e: E) -> Self {
+		Self { inner: Box::new(e) }
 	}
+}
 
-	fn nth(&mut self, n: usize) -> Option<Self::Item> {
-		if n >= self.len as usize {
-			return None;
-		}
-
-		self.cur = n as u32;
-		self.next()
+impl Device {
+	pub fn get_default(backend: &dyn Backend, flow: Flow) -> Result<Self> {
+		backend.default_device(flow)
 	}
 
-	fn next(&mut self) -> Option<Self::Item> {
-		if self.cur >= self.len {
-			None
-		} else {
-			let x = unsafe {
-				self.collection
-					.Item(self.cur)
-					.and_then(|dev| Device::new(dev))
-					.ok()
-			};
-			self.cur += 1;
-			x
-		}
+	/// Get the ID of the default device for the given role.
+	pub fn default_id(backend: &dyn Backend, flow: Flow, role: Role) -> Result<String> {
+		backend.default_id(flow, role)
 	}
-}
 
-impl Device {
-	unsafe fn new(dev: IMMDevice) -> Result<Self> {
-		let state = DeviceState(dev.GetState()?.0);
-		let vol = OnceCell::new();
-		let props = dev.OpenPropertyStore(STGM_READ)?;
-		let varname = props
-			.GetValue(&PKEY_Device_FriendlyName)?
-			.as_raw()
-			.Anonymous;
-		if varname.Anonymous.vt == VT_EMPTY.0 {
-			return Ok(Self {
-				dev,
-				vol,
-				state,
-				name: String::new(),
-			});
-		}
-
-		let name = PWSTR(varname.Anonymous.Anonymous.pwszVal);
-		if name.is_null() {
-			Ok(Self {
-				dev,
-				vol,
-				state,
-				name: String::new(),
-			})
-		} else {
-			Ok(Self {
-				dev,
-				vol,
-				state,
-				name: String::from_utf16_lossy(name.as_wide()),
-			})
-		}
+	pub fn enumerate(backend: &dyn Backend, flow: Flow, state: DeviceState) -> Result<Devices> {
+		backend.devices(flow, state)
 	}
 
-	unsafe fn enumerator() -> Result<IMMDeviceEnumerator> {
-		CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
-		let mm_enum: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-
-		Ok(mm_enum)
+	/// Get the friendly name of this device.
+	pub fn name(&self) -> &str {
+		self.inner.name()
 	}
 
-	#[inline]
-	unsafe fn vol(&self) -> Result<&IAudioEndpointVolume> {
-		if let Some(x) = self.vol.get() {
-			return Ok(x);
-		}
-		let vol = self.dev.Activate(CLSCTX_ALL, None)?;
-		Ok(self.vol.get_or_init(move || vol))
+	/// Get the backend specific ID of this device.
+	pub fn id(&self) -> Result<String> {
+		self.inner.id()
 	}
-}
 
-impl Device {
-	pub fn get_default() -> Result<Self> {
-		unsafe {
-			let mm_enum = Self::enumerator()?;
-			let dev = mm_enum.GetDefaultAudioEndpoint(eRender, eConsole)?;
-			Self::new(dev)
-		}
+	pub fn state(&self) -> DeviceState {
+		self.inner.state()
 	}
 
-	pub fn enumerate(state: DeviceState) -> Result<Devices> {
-		unsafe {
-			let enumerator = Self::enumerator()?;
-			let enumerator = enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE(state.0))?;
+	/// Make this device the default device for every [Role].
+	pub fn set_default(&self) -> Result<()> {
+		self.inner.set_default()
+	}
 
-			Ok(Devices {
-				cur: 0,
-				len: enumerator.GetCount()?,
-				collection: enumerator,
-			})
-		}
+	/// Subscribe to changes of the backend's devices; see [Backend::subscribe].
+	pub fn subscribe_all(
+		backend: &dyn Backend,
+		flow: Flow,
+		tx: Sender<Result<()>>,
+	) -> Result<Subscription> {
+		backend.subscribe(flow, tx)
 	}
 
-	/// Get the friendly name of this device.
-	///
-	/// Reads the [PKEY_Device_FriendlyName](https://learn.microsoft.com/en-us/windows/win32/coreaudio/pkey-device-friendlyname) property.
-	pub fn name(&self) -> &str {
-		&self.name
+	/// Subscribe to changes of the levels and the mute state of this device.
+	pub fn subscribe(&self, tx: Sender<Result<()>>) -> Result<Subscription> {
+		self.inner.subscribe(tx)
 	}
 
-	pub fn id(&self) -> Result<PWSTR> {
-		unsafe { self.dev.GetId() }
+	/// Get the audio sessions of the applications using this device.
+	pub fn sessions(&self) -> Result<Vec<Session>> {
+		self.inner.sessions()
 	}
 
 	pub fn channels(&self) -> Result<u32> {
-		unsafe { self.vol()?.GetChannelCount() }
+		self.inner.channels()
+	}
+
+	/// Get the speaker position of every channel; `None` for channels without a known position.
+	pub fn channel_map(&self) -> Result<Vec<Option<Position>>> {
+		self.inner.channel_map()
 	}
 
 	pub fn master_volume(&self) -> Result<f32> {
-		unsafe { self.vol()?.GetMasterVolumeLevelScalar() }
+		self.inner.master_volume()
 	}
 
 	pub fn master_db(&self) -> Result<f32> {
-		unsafe { self.vol()?.GetMasterVolumeLevel() }
+		self.inner.master_db()
 	}
 
 	pub fn set_master_volume(&self, volume: f32) -> Result<()> {
-		unsafe { self.vol()?.SetMasterVolumeLevelScalar(volume, ptr::null()) }
+		self.inner.set_master_volume(volume)
+	}
+
+	pub fn is_muted(&self) -> Result<bool> {
+		self.inner.is_muted()
+	}
+
+	pub fn set_mute(&self, mute: bool) -> Result<()> {
+		self.inner.set_mute(mute)
 	}
 
 	pub fn channel_volume(&self, channel: u32) -> Result<f32> {
-		unsafe { self.vol()?.GetChannelVolumeLevelScalar(channel) }
+		self.inner.channel_volume(channel)
 	}
 
 	pub fn channel_db(&self, channel: u32) -> Result<f32> {
-		unsafe { self.vol()?.GetChannelVolumeLevel(channel) }
+		self.inner.channel_db(channel)
 	}
 
 	pub fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
-		unsafe {
-			self.vol()?
-				.SetChannelVolumeLevelScalar(channel, volume, ptr::null())
-		}
+		self.inner.set_channel_volume(channel, volume)
 	}
 
-	pub fn state(&self) -> DeviceState {
-		self.state
+	pub fn set_channel_volumes(&self, volumes: &[f32]) -> Result<()> {
+		self.inner.set_channel_volumes(volumes)
 	}
 
-	/// Get the description of this device.
-	///
-	/// Reads the [PKEY_Device_DeviceDesc](https://learn.microsoft.com/en-us/windows/win32/coreaudio/pkey-device-devicedesc) property.
-	pub fn _description(&self) -> Result<String> {
-		unsafe {
-			let props = self.dev.OpenPropertyStore(STGM_READ)?;
-			let varname = props.GetValue(&PKEY_Device_DeviceDesc)?.as_raw().Anonymous;
-			if varname.Anonymous.vt == VT_EMPTY.0 {
-				return Ok(String::new());
-			}
-
-			let desc = PWSTR(varname.Anonymous.Anonymous.pwszVal);
-			if desc.is_null() {
-				Ok(String::new())
-			} else {
-				Ok(String::from_utf16_lossy(desc.as_wide()))
-			}
-		}
+	/// Get the range of decibel levels of this device as `(min, max)`.
+	pub fn db_range(&self) -> Result<(f32, f32)> {
+		self.inner.db_range()
+	}
+
+	pub fn db_to_scalar(&self, db: f32) -> f32 {
+		self.inner.db_to_scalar(db)
+	}
+
+	pub fn scalar_to_db(&self, scalar: f32) -> f32 {
+		self.inner.scalar_to_db(scalar)
+	}
+
+	pub fn set_master_db(&self, db: f32) -> Result<()> {
+		self.inner.set_master_db(db)
+	}
+
+	pub fn set_channel_db(&self, channel: u32, db: f32) -> Result<()> {
+		self.inner.set_channel_db(channel, db)
 	}
 }
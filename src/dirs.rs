@@ -0,0 +1,24 @@
+use std::{
+	env,
+	path::PathBuf,
+};
+
+use crate::error::Result;
+
+/// The directory of wol's files: `%APPDATA%\wol` on Windows and `$XDG_CONFIG_HOME/wol` (`~/.config/wol` by default) elsewhere.
+pub fn config_dir() -> Result<PathBuf> {
+	let var = |name| {
+		env::var_os(name)
+			.filter(|s| !s.is_empty())
+			.map(PathBuf::from)
+	};
+
+	let base = if cfg!(windows) {
+		var("APPDATA")
+	} else {
+		var("XDG_CONFIG_HOME").or_else(|| var("HOME").map(|home| home.join(".config")))
+	};
+
+	base.map(|dir| dir.join("wol"))
+		.ok_or_else(|| "could not determine the configuration directory".into())
+}
@@ -4,6 +4,7 @@ use derive_more::{
 	Display,
 	From,
 };
+#[cfg(windows)]
 use windows::core::Error as WinError;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -12,8 +13,21 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub enum Error {
 	#[from(Cow<'static, str>, String, &'static str)]
 	Str(Cow<'static, str>),
+	/// A change refused by a safety check, such as the volume floor; --force overrides these.
+	Refused(Cow<'static, str>),
+	#[cfg(windows)]
 	#[from]
 	Win(WinError),
 }
 
+impl Error {
+	/// The exit code of the process when it fails with this error.
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Self::Refused(_) => 3,
+			_ => 1,
+		}
+	}
+}
+
 impl std::error::Error for Error {}
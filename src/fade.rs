@@ -0,0 +1,154 @@
+use std::{
+	sync::{
+		atomic::{
+			AtomicBool,
+			Ordering,
+		},
+		Arc,
+	},
+	thread,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+use crate::{
+	error::Result,
+	volume::Volume,
+};
+
+/// The default number of steps per second.
+pub const DEFAULT_RATE: u32 = 25;
+
+/// Parse a duration such as `3s`, `1.5s`, `500ms` or `2m`; a number without a unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, &'static str> {
+	let s = s.trim();
+	let (n, scale) = if let Some(n) = s.strip_suffix("ms") {
+		(n, 0.001)
+	} else if let Some(n) = s.strip_suffix('s') {
+		(n, 1.0)
+	} else if let Some(n) = s.strip_suffix('m') {
+		(n, 60.0)
+	} else {
+		(s, 1.0)
+	};
+
+	match n.trim().parse::<f64>() {
+		Ok(n) if n.is_finite() && n >= 0.0 => Ok(Duration::from_secs_f64(n * scale)),
+		_ => Err("the duration must be a non-negative number followed by 'ms', 's' or 'm', e.g. '3s' or '500ms'"),
+	}
+}
+
+/// Interpolate the levels of the device from their initial values to the pending values of `vol`.
+///
+/// Every channel takes its own duration to reach its target; channels with a zero duration are set right away.
+/// The channels move linearly, so channels that are scaled together (e.g. by a master adjustment) keep their ratios throughout.
+/// Channels that are set in decibels move linearly in decibels and are written as such, since their scalars are only estimates on some backends.
+/// Unmuting happens before the fade and muting happens after it.
+///
+/// If the fade is interrupted with Ctrl-C, the levels either stay where they are or jump to their targets depending on `snap`.
+/// Either way the pending values of `vol` are updated to what has been written.
+pub fn run(
+	vol: &mut Volume,
+	durations: &[Duration],
+	rate: u32,
+	snap: bool,
+	force: bool,
+) -> Result<()> {
+	vol.check(force)?;
+
+	let interrupted = Arc::new(AtomicBool::new(false));
+	{
+		let interrupted = Arc::clone(&interrupted);
+		ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+			.map_err(|e| format!("failed to set the Ctrl-C handler: {e}"))?;
+	}
+
+	let start = vol.init_channels().to_vec();
+	let target = vol.channels().to_vec();
+	// The start and target of the channels set in decibels; a silent start is the bottom of the range.
+	let mut db = Vec::with_capacity(target.len());
+	for c in 0..target.len() as u32 {
+		db.push(match vol.target_db(c)? {
+			Some(to) => {
+				let (min, _) = vol.device().db_range()?;
+				Some((vol.channel_active_db(c)?.max(min), to))
+			}
+			None => None,
+		});
+	}
+	let in_db = db.iter().any(Option::is_some);
+	let mute = vol.is_muted();
+	if !mute && vol.init_muted() {
+		vol.write_mute(false)?;
+	}
+
+	let total = durations.iter().copied().max().unwrap_or_default();
+	let step = Duration::from_secs_f64(1.0 / rate.max(1) as f64);
+	let began = Instant::now();
+	let mut current = start.clone();
+
+	let finished = loop {
+		if interrupted.load(Ordering::SeqCst) {
+			break snap;
+		}
+
+		let elapsed = began.elapsed();
+		let levels = (0..current.len())
+			.map(|c| {
+				let (from, to, d) = (start[c], target[c], durations[c]);
+				if elapsed >= d {
+					to
+				} else {
+					from + (to - from) * (elapsed.as_secs_f32() / d.as_secs_f32())
+				}
+			})
+			.collect::<Vec<_>>();
+
+		if levels != current {
+			if in_db {
+				for (c, (&new, &old)) in levels.iter().zip(&current).enumerate() {
+					match db[c] {
+						Some((from, to)) => {
+							let t = match durations[c] {
+								d if elapsed >= d => 1.0,
+								d => elapsed.as_secs_f32() / d.as_secs_f32(),
+							};
+							vol.write_channel_db(c as u32, from + (to - from) * t)?;
+						}
+						None if new != old => vol.write_channel(c as u32, new)?,
+						None => (),
+					}
+				}
+			} else {
+				vol.write_channels(&levels)?;
+			}
+			current = levels;
+		}
+
+		if elapsed >= total {
+			break true;
+		}
+		thread::sleep(step);
+	};
+
+	if finished {
+		if current != target && !in_db {
+			vol.write_channels(&target)?;
+		}
+		vol.write_db()?;
+		if mute && !vol.init_muted() {
+			vol.write_mute(true)?;
+		}
+	} else {
+		for (c, &level) in current.iter().enumerate() {
+			vol.set_channel(c as u32, level);
+		}
+		if mute && !vol.init_muted() {
+			vol.set_mute(false);
+		}
+	}
+
+	Ok(())
+}
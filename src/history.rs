@@ -0,0 +1,257 @@
+use std::{
+	fs,
+	io::ErrorKind,
+	path::PathBuf,
+	time::{
+		SystemTime,
+		UNIX_EPOCH,
+	},
+};
+
+use serde_json::{
+	json,
+	Value as Json,
+};
+
+use crate::{
+	device::Flow,
+	dirs,
+	error::Result,
+	profile::{
+		self,
+		Levels,
+	},
+	volume::Volume,
+};
+
+/// The number of changes kept in the history; older ones are dropped.
+pub const MAX_ENTRIES: usize = 100;
+
+/// A change of the levels of a device.
+pub struct Entry {
+	/// Seconds since the Unix epoch.
+	pub time: u64,
+	pub id: String,
+	pub name: String,
+	pub flow: Flow,
+	pub before: Levels,
+	pub after: Levels,
+}
+
+/// The changes made by wol, oldest first.
+#[derive(Default)]
+pub struct History(Vec<Entry>);
+
+impl Entry {
+	/// The change from the levels of `vol` before any adjustment to its pending levels.
+	///
+	/// Returns `None` if the levels haven't changed.
+	pub fn new(vol: &Volume, flow: Flow) -> Result<Option<Self>> {
+		let before = Levels::initial(vol);
+		let after = Levels::pending(vol);
+		if before == after {
+			return Ok(None);
+		}
+
+		Ok(Some(Self {
+			time: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_or(0, |d| d.as_secs()),
+			id: vol.device().id()?,
+			name: vol.device().name().to_string(),
+			flow,
+			before,
+			after,
+		}))
+	}
+
+	pub fn to_json(&self) -> Json {
+		json!({
+			"time": self.time,
+			"id": self.id,
+			"name": self.name,
+			"flow": self.flow.to_string(),
+			"before": self.before.to_json(),
+			"after": self.after.to_json(),
+		})
+	}
+
+	fn from_json(x: &Json) -> Option<Self> {
+		Some(Self {
+			time: x["time"].as_u64()?,
+			id: x["id"].as_str()?.to_string(),
+			name: x["name"].as_str()?.to_string(),
+			flow: profile::parse_flow(x["flow"].as_str()?)?,
+			before: Levels::from_json(&x["before"])?,
+			after: Levels::from_json(&x["after"])?,
+		})
+	}
+
+	/// The time of the change as `YYYY-MM-DD HH:MM:SS` in UTC.
+	pub fn time_utc(&self) -> String {
+		let days = (self.time / 86400) as i64;
+		let secs = self.time % 86400;
+
+		// Convert days since the epoch to a civil date; see http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+		let z = days + 719468;
+		let era = z.div_euclid(146097);
+		let doe = z.rem_euclid(146097);
+		let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+		let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+		let mp = (5 * doy + 2) / 153;
+		let day = doy - (153 * mp + 2) / 5 + 1;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 };
+		let year = yoe + era * 400 + i64::from(month <= 2);
+
+		format!(
+			"{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+			secs / 3600,
+			secs / 60 % 60,
+			secs % 60
+		)
+	}
+}
+
+impl History {
+	/// Read the history file; a missing file is an empty history and invalid lines are skipped.
+	pub fn load() -> Result<Self> {
+		let path = path()?;
+		let s = match fs::read_to_string(&path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+			Err(e) => return Err(format!("failed to read {}: {e}", path.display()).into()),
+		};
+
+		Ok(Self(
+			s.lines()
+				.filter_map(|s| serde_json::from_str::<Json>(s).ok())
+				.filter_map(|x| Entry::from_json(&x))
+				.collect(),
+		))
+	}
+
+	pub fn save(&self) -> Result<()> {
+		let path = path()?;
+		let mut s = String::new();
+		for e in &self.0 {
+			s += &e.to_json().to_string();
+			s.push('\n');
+		}
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)
+				.map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+		}
+		fs::write(&path, s).map_err(|e| format!("failed to write {}: {e}", path.display()).into())
+	}
+
+	pub fn entries(&self) -> &[Entry] {
+		&self.0
+	}
+
+	/// Add an entry, dropping the oldest ones past [MAX_ENTRIES].
+	pub fn push(&mut self, e: Entry) {
+		self.0.push(e);
+		if self.0.len() > MAX_ENTRIES {
+			self.0.drain(..self.0.len() - MAX_ENTRIES);
+		}
+	}
+
+	/// Remove the last `n` entries, returning them newest first.
+	pub fn pop(&mut self, n: usize) -> Result<Vec<Entry>> {
+		match self.0.len() {
+			0 => Err("the history is empty".into()),
+			len if n > len => {
+				Err(format!("cannot undo {n} changes; the history only has {len}").into())
+			}
+			len => Ok(self.0.drain(len - n..).rev().collect()),
+		}
+	}
+}
+
+/// Append an entry to the history file.
+pub fn record(e: Entry) -> Result<()> {
+	let mut history = History::load()?;
+	history.push(e);
+	history.save()
+}
+
+fn path() -> Result<PathBuf> {
+	Ok(dirs::config_dir()?.join("history.jsonl"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn levels(master: f32) -> Levels {
+		Levels {
+			master,
+			channels: vec![master, master],
+			muted: false,
+		}
+	}
+
+	fn entry(time: u64) -> Entry {
+		Entry {
+			time,
+			id: String::from("memory:0"),
+			name: String::from("Speakers"),
+			flow: Flow::Capture,
+			before: levels(0.5),
+			after: levels(0.25),
+		}
+	}
+
+	#[test]
+	fn json() {
+		let e = Entry::from_json(&entry(1700000000).to_json()).unwrap();
+		assert_eq!(e.time, 1700000000);
+		assert_eq!((e.id.as_str(), e.name.as_str()), ("memory:0", "Speakers"));
+		assert_eq!(e.flow, Flow::Capture);
+		assert!(e.before == levels(0.5) && e.after == levels(0.25));
+
+		let mut x = entry(0).to_json();
+		x["flow"] = json!("sideways");
+		assert!(Entry::from_json(&x).is_none());
+		assert!(Entry::from_json(&json!({"time": 0})).is_none());
+	}
+
+	#[test]
+	fn time_utc() {
+		assert_eq!(entry(0).time_utc(), "1970-01-01 00:00:00");
+		assert_eq!(entry(951782400).time_utc(), "2000-02-29 00:00:00");
+		assert_eq!(entry(1700000000).time_utc(), "2023-11-14 22:13:20");
+		assert_eq!(entry(4107542399).time_utc(), "2100-02-28 23:59:59");
+	}
+
+	#[test]
+	fn push_and_pop() {
+		let mut history = History::default();
+		assert_eq!(
+			history.pop(1).err().unwrap().to_string(),
+			"the history is empty"
+		);
+
+		for time in 0..MAX_ENTRIES as u64 + 5 {
+			history.push(entry(time));
+		}
+		assert_eq!(history.entries().len(), MAX_ENTRIES);
+		assert_eq!(history.entries()[0].time, 5);
+
+		assert_eq!(
+			history.pop(MAX_ENTRIES + 1).err().unwrap().to_string(),
+			"cannot undo 101 changes; the history only has 100"
+		);
+		assert_eq!(history.entries().len(), MAX_ENTRIES);
+
+		let popped = history.pop(3).unwrap();
+		assert_eq!(
+			popped.iter().map(|e| e.time).collect::<Vec<_>>(),
+			[104, 103, 102]
+		);
+		assert_eq!(history.entries().len(), MAX_ENTRIES - 3);
+		assert_eq!(history.pop(MAX_ENTRIES - 3).unwrap().len(), MAX_ENTRIES - 3);
+		assert!(history.entries().is_empty());
+	}
+}
@@ -0,0 +1,130 @@
+use serde_json::{
+	json,
+	Value as Json,
+};
+
+use crate::{
+	device::{
+		Device,
+		Role,
+	},
+	session::Session,
+	volume::Volume,
+};
+
+/// Convert a level to a JSON number; infinities and NaN become null.
+///
+/// Going through the string representation keeps e.g. 0.33 from turning into 0.33000001311302185.
+pub fn num(x: f32) -> Json {
+	x.to_string()
+		.parse::<f64>()
+		.ok()
+		.and_then(serde_json::Number::from_f64)
+		.map_or(Json::Null, Json::Number)
+}
+
+/// A level as a scalar between 0 and 1 and in decibels; the decibels are null if the backend can't report them.
+fn level(scalar: f32, db: Option<f32>) -> Json {
+	json!({
+		"scalar": num(scalar),
+		"db": db.map_or(Json::Null, num),
+	})
+}
+
+/// The master, channel and mute fields; the decibels are read from `dev`, if given.
+fn levels(master: f32, channels: &[f32], muted: bool, dev: Option<&Device>) -> Json {
+	json!({
+		"master": level(master, dev.and_then(|d| d.master_db().ok())),
+		"channels": channels
+			.iter()
+			.enumerate()
+			.map(|(c, &x)| level(x, dev.and_then(|d| d.channel_db(c as u32).ok())))
+			.collect::<Vec<_>>(),
+		"muted": muted,
+	})
+}
+
+/// The status of the device (or session) behind `vol`.
+///
+/// The levels are the pending ones, like in the text output.
+/// For dry runs, the levels before and after the adjustments are included as well; the decibels after them are null since the changes are not applied.
+/// If guards were given, `guarded` tells whether any of them changed the levels.
+pub fn status(vol: &Volume, dry: bool, guarded: Option<bool>) -> Json {
+	let dev = vol.device();
+	let mut x = json!({
+		"name": dev.name(),
+		"id": dev.id().ok(),
+		"state": dev.state().to_string(),
+		"channel_count": vol.chan_count(),
+	});
+
+	let after = levels(
+		vol.master(),
+		vol.channels(),
+		vol.is_muted(),
+		(!dry).then_some(dev),
+	);
+	merge(&mut x, &after);
+	if let Some(changed) = guarded {
+		x["guarded"] = json!(changed);
+	}
+
+	if dry {
+		x["dry_run"] = json!(true);
+		x["before"] = levels(
+			vol.init_master(),
+			vol.init_channels(),
+			vol.init_muted(),
+			Some(dev),
+		);
+		x["after"] = after;
+	}
+
+	x
+}
+
+/// A device as listed by --list.
+pub fn device(dev: &Device, default_for: &[Role]) -> Json {
+	let channels = dev.channels().unwrap_or(0);
+	let volumes = (0..channels)
+		.map(|c| dev.channel_volume(c).unwrap_or(0.0))
+		.collect::<Vec<_>>();
+
+	let mut x = json!({
+		"name": dev.name(),
+		"id": dev.id().ok(),
+		"state": dev.state().to_string(),
+		"channel_count": channels,
+		"default_for": default_for.iter().map(Role::to_string).collect::<Vec<_>>(),
+	});
+	merge(
+		&mut x,
+		&levels(
+			dev.master_volume().unwrap_or(0.0),
+			&volumes,
+			dev.is_muted().unwrap_or(false),
+			Some(dev),
+		),
+	);
+
+	x
+}
+
+/// An application session as listed by --list-apps.
+pub fn session(s: &Session) -> Json {
+	json!({
+		"process": s.process(),
+		"pid": s.pid(),
+		"state": s.state().to_string(),
+		"volume": s.control().master_volume().map_or(Json::Null, num),
+		"muted": s.control().is_muted().unwrap_or(false),
+	})
+}
+
+fn merge(x: &mut Json, from: &Json) {
+	if let (Json::Object(x), Json::Object(from)) = (x, from) {
+		for (k, v) in from {
+			x.insert(k.clone(), v.clone());
+		}
+	}
+}
@@ -1,25 +1,68 @@
 mod args;
+mod backend;
+mod config;
 mod device;
+mod dirs;
 mod error;
+mod fade;
+mod history;
+mod json;
+mod profile;
 mod screen_reader;
+mod session;
+mod template;
 mod volume;
 
 use std::{
 	env,
+	fmt::{
+		self,
+		Write,
+	},
 	num::IntErrorKind,
 	process::exit,
+	sync::mpsc,
+	thread,
+	time::Duration,
 };
 
 use self::{
+	backend::Backend,
+	config::{
+		Alias,
+		Config,
+	},
 	device::{
 		Device,
 		DeviceState,
+		Flow,
+		Position,
+		Role,
+	},
+	error::{
+		Error,
+		Result,
+	},
+	history::History,
+	profile::{
+		Levels,
+		Profile,
+	},
+	session::Session,
+	template::Template,
+	volume::{
+		Floor,
+		FloorPolicy,
+		Limit,
+		LimitAction,
+		PanLaw,
+		Volume,
 	},
-	error::Result,
-	volume::Volume,
 };
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// How long --watch waits for more changes before printing the status.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(50);
 
 fn help_and_exit() {
 	println!(
@@ -32,12 +75,55 @@ OPTIONS:
   -d, --device=<name>: Specify a device name; the string will be matched as a substring case-insensitively
   -i, --id=<id>: Specify a device ID
   -l, --list: Show a list of audio output devices
-  -f, --force: If a screen reader is running and the volume goes below 5%, do not refuse to apply the new volume
+  --app=<name|pid>: Adjust the audio session of an application on the device instead of the device itself; names are matched case-insensitively and the .exe suffix is optional
+  --list-apps: Show a list of the application audio sessions on the device
+  --save=<name>: After any adjustments, save the levels and the mute state of the device to a profile
+  --restore=<name>: Restore the levels and the mute state saved to a profile on the device they were saved from; cannot be combined with adjustments or a device selection
+    Profiles are stored in the "wol/profiles" folder of the user's configuration directory (e.g. ~/.config or %APPDATA%)
+  --undo[=<N>]: Undo the last N changes made by wol (default: 1), restoring the levels of every device they touched; undone changes are removed from the history
+  --history: Show the changes made by wol, numbered for --undo; the last 100 changes are kept
+  --set-default=<name|id>: Make the device with the given ID or name the default device for every role; adjustments are then applied to it
+  --capture, --input: Operate on audio input devices (e.g. microphones) instead of output devices
+  --backend=<name>: Specify the audio backend; overrides the WOL_BACKEND environment variable
+  --config=<path>: Read the configuration from the given file; overrides the WOL_CONFIG environment variable
+  --fade=<duration>: Fade to the new levels over the duration instead of jumping to them; e.g. "3s" or "500ms"
+  --fade-rate=<N>: The number of steps per second of fades (default: 25)
+  --fade-snap: If a fade is interrupted with Ctrl-C, jump to the new levels instead of stopping where they are
+  --max=<level>: The maximum level of the device as a percentage or in decibels, e.g. "60" or "-12db"; overrides the configuration file
+    Adjustments above it are lowered to it with a warning, or refused if max_action is "refuse" in the configuration file; levels already above it are not lowered
+  -f, --force: Ignore the maximum level and the floor; --no-force turns off force = true of the configuration file or WOL_FORCE
+  --floor=<N>: Refuse to lower the volume below N% or to mute the device (default: 5); 0 turns this off; does not apply to --app
+  --floor-policy=<screen-reader|always>: Whether the floor applies only while a screen reader is running (the default) or always
+  --confirm: Ask for confirmation on the terminal instead of refusing to go below the floor
+    Changes refused for going below the floor or above the maximum level make wol exit with code 3
+  -w, --watch: Keep running and print the levels every time they change; follows the default device unless --device or --id is given
   -n, --dry-run: Do not actually apply the changes
-  -q, --quiet: After modifications, do not print the new volume levels
+  --format=<text|json|template>: The output format of the levels, --list and --list-apps (default: text); JSON output of dry runs includes the levels before and after the adjustments
+    A template such as '{{master}}% {{dev.name}}' only changes the output of the levels; placeholders are written as {{name}} or {{name:spec}}
+    Placeholders: master, master_db, ch<N>, ch<N>_db, l, r, l_db, r_db, balance (-100 to 100), muted ("muted" or empty), dev.name, dev.id, dev.state, dev.channels
+    The spec is [[fill]align][0][width][.precision] where align is '<', '^' or '>'; e.g. {{master:>3}} or {{master_db:.1}}; levels are rounded to --precision decimal places by default
+  --pan-law=<linear|constant-power>: How the left and right channels follow balance adjustments (default: linear); with constant-power, the channels follow the cosine and sine of the pan angle
+  --precision=<N>: The number of decimal places of the displayed levels (default: 0)
+  -q, --quiet: After modifications, do not print the new volume levels; --no-quiet turns off quiet = true of the configuration file or WOL_QUIET
   -h, --help: Show help
   -V, --version: Show version information
 
+CONFIGURATION:
+  Defaults can be set in the TOML file "wol/config.toml" in the user's configuration directory (e.g. ~/.config or %APPDATA%)
+  Command line flags take precedence over environment variables, which take precedence over the configuration file
+  The environment variables are WOL_BACKEND, WOL_DEVICE, WOL_ID, WOL_FORMAT, WOL_QUIET and WOL_FORCE (1 or 0)
+  Keys:
+    device = "<name>": The device used unless --device or --id is given; may be an alias
+    id = "<id>": Like device, for a device ID
+    backend = "<name>": The audio backend
+    quiet = true, force = true: Turn on --quiet or --force by default
+    format = "<text|json|template>": The output format
+    max = <level>: The maximum level of every device, e.g. 80 or "-12db"
+    [device_max]: The maximum levels of devices given by alias, ID or name, e.g. hp = "-12db"
+    max_action = "<clamp|refuse>": Whether adjustments above the maximum are lowered to it (the default) or refused unless --force is given
+    floor = <N>, floor_policy = "<screen-reader|always>", confirm = true: Defaults for --floor, --floor-policy and --confirm
+    [aliases]: Short names for devices usable with --device and --set-default, e.g. hp = {{ id = "..." }} or tv = {{ name = "HDMI" }}
+
 ADJUSTMENT:
   Adjustments have the syntax <channel><operation><value>
 
@@ -46,19 +132,41 @@ ADJUSTMENT:
     - 'R': Right channel
     - 'M': Master
     - 'A': All channels
+    - 'B': The balance between the left and right channels, from -100 (left) to 100 (right); e.g. "b=-20" (20% to the left) or "b=0" (center)
+      The louder of the two channels keeps its level, so the master volume doesn't change
     - <N>: Channel N where N is between 0 and 2^32
+    - A speaker position: 'FL', 'FR', 'C', 'LFE', 'BL', 'BR', 'SL', 'SR', 'FLC', 'FRC', 'BC' or 'MONO'; e.g. "lfe=30"
+    - A range of channels such as "2..5" or "4-10"; both ends are included
+    - A comma separated list of any of the channels above, except 'A', 'M' and 'B'; e.g. "0,1,4-10"
+    - A group of speaker positions: 'front', 'rear' (or 'back'), 'side' or 'surround' (rear and side)
 
-  <operation> can be one of '+' (increase volume), '-' (decrease volume) or '=' (set volume)
+  <operation> can be one of '+' (increase volume), '-' (decrease volume), '=' (set volume), '*' (multiply the volume) or '/' (divide the volume)
+    The value of '*' and '/' is a factor, e.g. "m*0.8" (make the master volume 20% quieter) or "a/2" (halve every channel); the result is clamped between 0 and 100
+    The guards '<=' (cap) and '>=' (floor) only change levels that are above or below the value, e.g. "m<=30" (lower the master volume to 30 if it is louder) or "a>=20"; most shells need them quoted
+    The output tells whether any guard changed the levels
 
     <value> Must be one of
-    - An integer from 0 to 100
+    - A number from 0 to 100, optionally followed by '%'; e.g. "33.5"
+    - A fraction from 0 to 1 followed by 'f', e.g. "0.335f"; numbers without it are always percentages, so "r-0.5" lowers the right channel by half a percent
+    - A level in decibels such as "-12db" or "1.5dB"; the result is clamped to the range of the device
     - 'L': the left channel's current volume
     - 'R': the right channel's current volume
     - 'M': current master volume
     - "c<N>" where <N> is an integer from 0 to 2^32: channel N's current volume
+    - A speaker position such as 'FL' or 'C': that channel's current volume
 
   If the <channel> value is not provided, the operation is done on the master volume level
 
+  The mute state of the device can be changed with "mute", "unmute" and "!" (toggle mute)
+
+  Channels can be swapped with <channels><><channels>, e.g. "l<>r" or "front<>rear"
+  A group, range or list on both sides of '=' mirrors the channels, e.g. "rear=front" (BL takes FL's level and BR takes FR's) or "2,3=0,1"
+    Sets of different sizes are paired by side and channels without a counterpart keep their level; e.g. with "surround=front" both the rear and side left speakers take FL's level
+    Swaps and mirrors read the levels from before any adjustment, so the order of the arguments doesn't matter; e.g. with "l<>r rear=front" the rear speakers mirror the front ones as they were before the swap
+
+  A level adjustment can be followed by ~<duration> to fade to the new level over the duration, overriding --fade
+  E.g. "m=20~2s" (fade the master volume to 20 over 2 seconds)
+
   As a shorthand to set the master volume, you can omit both <channel> and <operation>
   E.g. "100" (set master volume to max)
     As another shorthand to set the levels for channels 'L', 'R', 'A' or 'M', you can omit the = sign
@@ -73,148 +181,787 @@ fn err_exit<T: std::fmt::Display, O>(msg: T) -> O {
 	exit(1);
 }
 
+/// Read an environment variable; an empty one counts as unset.
+fn env_var(name: &str) -> Option<String> {
+	env::var(name).ok().filter(|s| !s.is_empty())
+}
+
 #[derive(Copy, Clone)]
 enum Op {
 	Set,
 	Inc,
 	Dec,
+	Mul,
+	Div,
+	/// Lower the level to the value only if it is above it.
+	Cap,
+	/// Raise the level to the value only if it is below it.
+	Floor,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 enum Channel {
 	Master,
 	All,
+	Balance,
+	/// One or more channels as given on the command line, e.g. `2`, `0,1,4-10` or `rear`.
+	List(Vec<Chan>),
+	/// The channel numbers of a [Channel::List], resolved with the device's channel map.
+	N(Vec<u32>),
+}
+
+/// An item of a channel list.
+#[derive(Copy, Clone)]
+enum Chan {
 	N(u32),
+	/// An inclusive range of channel numbers.
+	Range(u32, u32),
+	Position(Position),
+	Group(Group),
+}
+
+/// A named group of speaker positions.
+#[derive(Copy, Clone)]
+enum Group {
+	Front,
+	Rear,
+	Side,
+	Surround,
 }
 
 #[derive(Copy, Clone)]
 enum Value {
-	N(u8),
+	/// A level as a scalar between 0 and 1.
+	N(f32),
+	Db(f32),
+	/// The operand of a multiplication or a division.
+	Factor(f32),
+	/// A balance from -1 (left) to 1 (right).
+	Balance(f32),
 	MasterChannel,
 	Channel(u32),
+	Position(Position),
+}
+
+enum Format {
+	Text,
+	Json,
+	Template(Template),
 }
 
 #[derive(Copy, Clone)]
-struct Adjust {
-	op: Op,
-	chan: Channel,
-	val: Value,
+enum Mute {
+	On,
+	Off,
+	Toggle,
+}
+
+#[derive(Clone)]
+enum Adjust {
+	Level {
+		op: Op,
+		chan: Channel,
+		val: Value,
+		fade: Option<Duration>,
+	},
+	Mute(Mute),
+	/// Exchange the levels of two sets of channels, e.g. `l<>r` or `front<>rear`.
+	Swap {
+		a: Channel,
+		b: Channel,
+		fade: Option<Duration>,
+	},
+	/// Copy the levels of a set of channels to another, e.g. `rear=front`.
+	///
+	/// Once resolved, both sides are channel numbers and each channel in `to` copies the one at the same index in `from`;
+	/// a swap resolves to a mirror in both directions.
+	Mirror {
+		to: Channel,
+		from: Channel,
+		fade: Option<Duration>,
+	},
+}
+
+impl Format {
+	fn parse(s: &str) -> Result<Self, String> {
+		match s.to_lowercase().as_str() {
+			"text" => Ok(Self::Text),
+			"json" => Ok(Self::Json),
+			_ if s.contains(['{', '}']) => Template::parse(s).map(Self::Template),
+			_ => Err(String::from(
+				"the format must be 'text', 'json' or a template such as '{master}%'",
+			)),
+		}
+	}
 }
 
 impl Value {
 	fn parse(s: &str) -> Result<Self, &'static str> {
-		let x = match s {
-			"m" | "M" => Self::MasterChannel,
-			"l" | "L" => Self::Channel(0),
-			"r" | "R" => Self::Channel(1),
+		let x =
+			match s {
+				"m" | "M" => Self::MasterChannel,
+				"l" | "L" => Self::Channel(0),
+				"r" | "R" => Self::Channel(1),
+				_ => {
+					if let Some(n) = s
+						.strip_suffix(['b', 'B'])
+						.and_then(|s| s.strip_suffix(['d', 'D']))
+					{
+						let db = n
+							.parse::<f32>()
+							.ok()
+							.filter(|n| n.is_finite())
+							.ok_or("expected a number before 'db', e.g. '-12db' or '1.5db'")?;
+
+						Self::Db(db)
+					} else if let Some(pos) = Position::parse(s) {
+						Self::Position(pos)
+					} else if let Some(s) = s.strip_prefix(['c', 'C']) {
+						let n = s.parse::<u32>().map_err(|e| match e.kind() {
+							IntErrorKind::Empty => "missing a channel number after 'c'",
+							IntErrorKind::Zero => unreachable!(),
+							_ => {
+								"expected an channel number as an integer from 0 to 2^32 after 'c'"
+							}
+						})?;
+
+						Self::Channel(n)
+					} else if s.is_empty() {
+						return Err("missing a value");
+					} else {
+						// Numbers are percentages; a raw scalar is a fraction ending in 'f', e.g. 0.335f.
+						let (n, scale, err) = match s.strip_suffix(['f', 'F']) {
+						Some(n) => (n, 1.0, "a value ending in 'f' must be a fraction from 0 to 1, e.g. '0.335f'"),
+						None => (
+							s.strip_suffix('%').unwrap_or(s),
+							100.0,
+							"the value must be a number from 0 to 100",
+						),
+					};
+
+						match n.parse::<f32>() {
+							Ok(n) if (0.0..=scale).contains(&n) => Self::N(n / scale),
+							_ => return Err(err),
+						}
+					}
+				}
+			};
+
+		Ok(x)
+	}
+
+	fn parse_balance(s: &str) -> Result<Self, &'static str> {
+		match s.parse::<f32>() {
+			Ok(n) if (-100.0..=100.0).contains(&n) => Ok(Self::Balance(n / 100.0)),
+			_ => Err("the balance must be a number from -100 (left) to 100 (right)"),
+		}
+	}
+}
+
+impl Group {
+	fn parse(s: &str) -> Option<Self> {
+		Some(match s.to_ascii_lowercase().as_str() {
+			"front" => Self::Front,
+			"rear" | "back" => Self::Rear,
+			"side" => Self::Side,
+			"surround" => Self::Surround,
+			_ => return None,
+		})
+	}
+
+	fn positions(self) -> &'static [Position] {
+		use Position::*;
+		match self {
+			Self::Front => &[
+				FrontLeft,
+				FrontRight,
+				FrontCenter,
+				FrontLeftCenter,
+				FrontRightCenter,
+			],
+			Self::Rear => &[BackLeft, BackRight, BackCenter],
+			Self::Side => &[SideLeft, SideRight],
+			Self::Surround => &[BackLeft, BackRight, BackCenter, SideLeft, SideRight],
+		}
+	}
+}
+
+impl fmt::Display for Group {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Front => "front",
+			Self::Rear => "rear",
+			Self::Side => "side",
+			Self::Surround => "surround",
+		})
+	}
+}
+
+impl Chan {
+	fn parse(s: &str) -> Result<Self, &'static str> {
+		let range = s.split_once("..").or_else(|| s.split_once('-'));
+		if let Some((start, end)) = range {
+			return match (start.parse::<u32>(), end.parse::<u32>()) {
+				(Ok(start), Ok(end)) if start <= end => Ok(Self::Range(start, end)),
+				(Ok(_), Ok(_)) => {
+					Err("the start of a channel range must not be greater than its end")
+				}
+				_ => Err(
+					"a channel range must be two integers separated by '..' or '-', e.g. '2..5'",
+				),
+			};
+		}
+
+		Ok(match s {
+			"l" | "L" => Self::N(0),
+			"r" | "R" => Self::N(1),
 			_ => {
-				if let Some(s) = s.strip_prefix(['c', 'C']) {
-					let n = s.parse::<u32>().map_err(|e| match e.kind() {
-						IntErrorKind::Empty => "missing a channel number after 'c'",
-						IntErrorKind::Zero => unreachable!(),
-						_ => "expected an channel number as an integer from 0 to 2^32 after 'c'",
-					})?;
-
-					Self::Channel(n)
+				if let Some(pos) = Position::parse(s) {
+					Self::Position(pos)
+				} else if let Some(group) = Group::parse(s) {
+					Self::Group(group)
+				} else if let Ok(n) = s.parse::<u32>() {
+					Self::N(n)
 				} else {
-					Self::N(s.parse::<u8>().map_err(|e| match e.kind() {
-						IntErrorKind::Empty => "missing a value",
-						IntErrorKind::Zero => unreachable!(),
-						_ => "the value must be an integer from 0 to 100",
-					})?)
+					return Err("the channel value must be one of 'L', 'R', 'A', 'M', 'B', a speaker position such as 'FL' or 'LFE', a group such as 'rear', an integer between 0 and 2^32 or a list or range of these");
 				}
 			}
-		};
-
-		Ok(x)
+		})
 	}
 }
 
 impl Channel {
 	fn parse(s: &str) -> Result<Self, &'static str> {
 		Ok(match s {
-			"l" | "0" | "L" => Self::N(0),
-			"r" | "1" | "R" => Self::N(1),
 			"a" | "A" => Self::All,
+			"b" | "B" => Self::Balance,
 			"" | "m" | "M" => Self::Master,
-			_ => return Err("the channel value must be one of 'L', 'R', 'A', 'M' or an integer between 0 and 2^32"),
+			_ => Self::List(s.split(',').map(Chan::parse).collect::<Result<_, _>>()?),
 		})
 	}
+
+	/// Replace a channel list with the channel numbers it refers to on the device.
+	fn resolve(&self, vol: &Volume) -> Result<Self> {
+		let Self::List(chans) = self else {
+			return Ok(self.clone());
+		};
+
+		let mut ns = Vec::new();
+		for c in chans {
+			match *c {
+				Chan::N(n) => ns.push(n),
+				Chan::Range(start, end) => ns.extend(start..=end),
+				Chan::Position(pos) => ns.push(
+					vol.find_position(pos)
+						.ok_or_else(|| format!("the device has no {pos} channel"))?,
+				),
+				Chan::Group(group) => {
+					let len = ns.len();
+					ns.extend(
+						group
+							.positions()
+							.iter()
+							.filter_map(|&pos| vol.find_position(pos)),
+					);
+					if ns.len() == len {
+						return Err(format!("the device has no {group} channels").into());
+					}
+				}
+			}
+		}
+
+		// A channel listed twice is adjusted once.
+		let mut seen = Vec::with_capacity(ns.len());
+		ns.retain(|n| {
+			let new = !seen.contains(n);
+			seen.push(*n);
+			new
+		});
+		Ok(Self::N(ns))
+	}
+
+	/// The channel numbers of a resolved channel list or of all channels.
+	fn numbers(&self, vol: &Volume) -> Vec<u32> {
+		match self {
+			Self::N(ns) => ns.clone(),
+			Self::All => (0..vol.chan_count()).collect(),
+			Self::Master | Self::Balance | Self::List(_) => unreachable!(),
+		}
+	}
+}
+
+/// Pair the channels of `a` with those of `b` for a swap or a mirror.
+///
+/// Sets of the same size are paired in order. Otherwise the channels are paired by side and channels without a counterpart are left out,
+/// e.g. the rear left speaker pairs with the front left one while the center has no rear counterpart.
+/// With `unique`, every channel of `b` is paired at most once.
+fn pair(vol: &Volume, a: &[u32], b: &[u32], unique: bool) -> Vec<(u32, u32)> {
+	if a.len() == b.len() {
+		return a.iter().copied().zip(b.iter().copied()).collect();
+	}
+
+	let mut pairs = Vec::<(u32, u32)>::new();
+	for &x in a {
+		let Some(pos) = vol.position(x) else {
+			continue;
+		};
+		let y = b.iter().copied().find(|&y| {
+			vol.position(y).map(side) == Some(side(pos))
+				&& !(unique && pairs.iter().any(|&(_, p)| p == y))
+		});
+		if let Some(y) = y {
+			pairs.push((x, y));
+		}
+	}
+
+	pairs
+}
+
+/// Which side of the listener a speaker is on: -1 for the left, 0 for the center and 1 for the right.
+fn side(pos: Position) -> i8 {
+	use Position::*;
+	match pos {
+		FrontLeft | BackLeft | FrontLeftCenter | SideLeft => -1,
+		FrontRight | BackRight | FrontRightCenter | SideRight => 1,
+		Mono | FrontCenter | Lfe | BackCenter => 0,
+	}
 }
 
 impl Adjust {
 	fn parse(s: &str) -> Result<Self, &'static str> {
-		let Some(i) = s.find(['+', '-', '=']) else {
-			let (chan, s) = s
-				.strip_prefix(['L', 'l'])
-				.map(|s| (Channel::N(0), s))
-				.or_else(|| s.strip_prefix(['R', 'r']).map(|s| (Channel::N(1), s)))
-				.or_else(|| s.strip_prefix(['a', 'A']).map(|s| (Channel::All, s)))
-				.or_else(|| s.strip_prefix(['m', 'M']).map(|s| (Channel::Master, s)))
-				.unwrap_or((Channel::Master, s));
-
-			let val = Value::parse(s)?;
-			return Ok(Self {
-				op: Op::Set,
-				chan,
-				val,
-			});
+		if s.eq_ignore_ascii_case("mute") {
+			return Ok(Self::Mute(Mute::On));
+		} else if s.eq_ignore_ascii_case("unmute") {
+			return Ok(Self::Mute(Mute::Off));
+		} else if s == "!" {
+			return Ok(Self::Mute(Mute::Toggle));
+		}
+
+		let (s, fade) = match s.split_once('~') {
+			Some((s, d)) => (s, Some(fade::parse_duration(d)?)),
+			None => (s, None),
+		};
+		if fade.is_some()
+			&& (s.eq_ignore_ascii_case("mute") || s.eq_ignore_ascii_case("unmute") || s == "!")
+		{
+			return Err("only level adjustments can be faded");
+		}
+
+		if let Some((a, b)) = s.split_once("<>") {
+			return match (Channel::parse(a)?, Channel::parse(b)?) {
+				(a @ Channel::List(_), b @ Channel::List(_)) => Ok(Self::Swap { a, b, fade }),
+				_ => Err("only channels can be swapped, e.g. 'l<>r' or 'front<>rear'"),
+			};
+		}
+
+		// A group, range or list on the right of '=' mirrors channels rather than copying a single level, e.g. "rear=front".
+		if let Some((to, from)) = s.split_once('=') {
+			if let (Ok(to @ (Channel::List(_) | Channel::All)), Ok(Channel::List(from))) =
+				(Channel::parse(to), Channel::parse(from))
+			{
+				if from.len() > 1 || matches!(from[0], Chan::Range(..) | Chan::Group(_)) {
+					return Ok(Self::Mirror {
+						to,
+						from: Channel::List(from),
+						fade,
+					});
+				}
+			}
+		}
+
+		// Channel ranges such as "4-10" contain an operator, so every operator is tried until both sides parse.
+		// If none does, the error is the one of the last operator, as in "1-0=3", unless that operator is the sign of a value such as "l=-5".
+		let mut err = None;
+		for (i, c) in s.char_indices().filter(|&(_, c)| "+-=*/".contains(c)) {
+			// The guards "<=" and ">=" are passed on as '<' and '>'.
+			let (chan, c) = match (c, s[..i].strip_suffix(['<', '>'])) {
+				('=', Some(chan)) => (chan, s.as_bytes()[i - 1] as char),
+				_ => (&s[..i], c),
+			};
+			match Self::parse_level(chan, c, &s[i + 1..], fade) {
+				Ok(x) => return Ok(x),
+				Err(e) => {
+					let sign = i > 0 && "+-=*/".contains(s.as_bytes()[i - 1] as char);
+					if !sign || err.is_none() {
+						err = Some(e);
+					}
+				}
+			}
+		}
+		if let Some(e) = err {
+			return Err(e);
+		}
+
+		let (chan, s) = s
+			.strip_prefix(['L', 'l'])
+			.map(|s| (Channel::List(vec![Chan::N(0)]), s))
+			.or_else(|| {
+				s.strip_prefix(['R', 'r'])
+					.map(|s| (Channel::List(vec![Chan::N(1)]), s))
+			})
+			.or_else(|| s.strip_prefix(['a', 'A']).map(|s| (Channel::All, s)))
+			.or_else(|| s.strip_prefix(['m', 'M']).map(|s| (Channel::Master, s)))
+			.or_else(|| s.strip_prefix(['b', 'B']).map(|s| (Channel::Balance, s)))
+			.unwrap_or((Channel::Master, s));
+
+		let val = match chan {
+			Channel::Balance => Value::parse_balance(s)?,
+			_ => Value::parse(s)?,
 		};
+		Ok(Self::Level {
+			op: Op::Set,
+			chan,
+			val,
+			fade,
+		})
+	}
 
-		let op = match &s[i..i + 1] {
-			"+" => Op::Inc,
-			"-" => Op::Dec,
-			"=" => Op::Set,
+	fn parse_level(
+		chan: &str,
+		op: char,
+		val: &str,
+		fade: Option<Duration>,
+	) -> Result<Self, &'static str> {
+		let op = match op {
+			'+' => Op::Inc,
+			'-' => Op::Dec,
+			'=' => Op::Set,
+			'*' => Op::Mul,
+			'/' => Op::Div,
+			'<' => Op::Cap,
+			'>' => Op::Floor,
 			_ => unreachable!(),
 		};
 
-		let chan = Channel::parse(&s[..i])?;
-		let val = Value::parse(&s[i + 1..])?;
+		let chan = Channel::parse(chan)?;
+		let val = match op {
+			Op::Mul | Op::Div => match val.parse::<f32>() {
+				Ok(n) if n == 0.0 && matches!(op, Op::Div) => return Err("cannot divide by 0"),
+				Ok(n) if n.is_finite() && n >= 0.0 => Value::Factor(n),
+				_ => return Err("expected a non-negative number after '*' or '/', e.g. '0.8'"),
+			},
+			_ if matches!(chan, Channel::Balance) => Value::parse_balance(val)?,
+			_ => Value::parse(val)?,
+		};
 
-		Ok(Self { op, chan, val })
+		Ok(Self::Level {
+			op,
+			chan,
+			val,
+			fade,
+		})
 	}
 
-	fn apply(self, vol: &mut Volume) {
-		let val = match self.val {
-			Value::N(n) => n as f32 / 100.0,
+	fn is_guard(&self) -> bool {
+		matches!(
+			self,
+			Self::Level {
+				op: Op::Cap | Op::Floor,
+				..
+			}
+		)
+	}
+
+	/// Check the channel numbers against the number of channels of the device.
+	fn validate(&self, chan_count: u32) -> Result<()> {
+		let (chans, val) = match self {
+			Self::Level { chan, val, .. } => ([chan, &Channel::Master], Some(*val)),
+			Self::Swap { a, b, .. } => ([a, b], None),
+			Self::Mirror { to, from, .. } => ([to, from], None),
+			Self::Mute(_) => return Ok(()),
+		};
+
+		let mut max = match val {
+			Some(Value::Channel(c)) => Some(c),
+			_ => None,
+		};
+		for chan in chans {
+			let Channel::List(chans) = chan else {
+				continue;
+			};
+			for c in chans {
+				let n = match *c {
+					Chan::N(n) | Chan::Range(_, n) => n,
+					Chan::Position(_) | Chan::Group(_) => continue,
+				};
+				max = max.max(Some(n));
+			}
+		}
+
+		match max {
+			Some(n) if n >= chan_count => {
+				Err(format!("the device only has {chan_count} channels").into())
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Replace the channel lists, speaker positions and groups with the channel numbers they have on the device.
+	///
+	/// Swaps and mirrors become mirrors between channel numbers.
+	fn resolve(&self, vol: &Volume) -> Result<Self> {
+		match self {
+			Self::Level {
+				op,
+				chan,
+				val,
+				fade,
+			} => {
+				let val = match *val {
+					Value::Position(pos) => Value::Channel(
+						vol.find_position(pos)
+							.ok_or_else(|| format!("the device has no {pos} channel"))?,
+					),
+					val => val,
+				};
+
+				Ok(Self::Level {
+					op: *op,
+					chan: chan.resolve(vol)?,
+					val,
+					fade: *fade,
+				})
+			}
+			Self::Swap { a, b, fade } => {
+				let a = a.resolve(vol)?.numbers(vol);
+				let b = b.resolve(vol)?.numbers(vol);
+				let pairs = pair(vol, &a, &b, true);
+				if pairs.is_empty() {
+					return Err(format!(
+						"cannot swap {} channels with {} channels",
+						a.len(),
+						b.len()
+					)
+					.into());
+				}
+
+				let (a, b): (Vec<_>, Vec<_>) = pairs.into_iter().unzip();
+				Ok(Self::Mirror {
+					to: Channel::N([&a[..], &b[..]].concat()),
+					from: Channel::N([&b[..], &a[..]].concat()),
+					fade: *fade,
+				})
+			}
+			Self::Mirror { to, from, fade } => {
+				let to = to.resolve(vol)?.numbers(vol);
+				let from = from.resolve(vol)?.numbers(vol);
+				let pairs = pair(vol, &to, &from, false);
+				if pairs.is_empty() {
+					return Err(format!(
+						"cannot mirror {} channels onto {} channels",
+						from.len(),
+						to.len()
+					)
+					.into());
+				}
+
+				let (to, from) = pairs.into_iter().unzip();
+				Ok(Self::Mirror {
+					to: Channel::N(to),
+					from: Channel::N(from),
+					fade: *fade,
+				})
+			}
+			Self::Mute(_) => Ok(self.clone()),
+		}
+	}
+
+	fn apply(&self, vol: &mut Volume) -> Result<()> {
+		let (op, chan, val) = match self {
+			Self::Level { op, chan, val, .. } => (*op, chan, *val),
+			Self::Mute(m) => {
+				let mute = match m {
+					Mute::On => true,
+					Mute::Off => false,
+					Mute::Toggle => !vol.is_muted(),
+				};
+				vol.set_mute(mute);
+				return Ok(());
+			}
+			Self::Mirror {
+				to: Channel::N(to),
+				from: Channel::N(from),
+				..
+			} => {
+				vol.mirror(to, from);
+				return Ok(());
+			}
+			Self::Swap { .. } | Self::Mirror { .. } => unreachable!(),
+		};
+
+		if let Channel::Balance = chan {
+			let old = vol.balance().unwrap_or(0.0);
+			let new = match (op, val) {
+				(Op::Set, Value::Balance(b)) => b,
+				(Op::Inc, Value::Balance(b)) => old + b,
+				(Op::Dec, Value::Balance(b)) => old - b,
+				(Op::Mul, Value::Factor(n)) => old * n,
+				(Op::Div, Value::Factor(n)) => old / n,
+				(Op::Cap, Value::Balance(b)) if old > b => b,
+				(Op::Floor, Value::Balance(b)) if old < b => b,
+				(Op::Cap | Op::Floor, _) => return Ok(()),
+				_ => unreachable!(),
+			};
+			return vol.set_balance(new);
+		}
+
+		if let Value::Db(db) = val {
+			// The current level is only needed for relative changes and guards, which fail if the device doesn't report decibels.
+			// A guard that already holds leaves the level alone.
+			let new = move |old: Result<f32>| -> Result<Option<f32>> {
+				Ok(match op {
+					Op::Set => Some(db),
+					Op::Inc => Some(old? + db),
+					Op::Dec => Some(old? - db),
+					Op::Cap => (old? > db).then_some(db),
+					Op::Floor => (old? < db).then_some(db),
+					Op::Mul | Op::Div => unreachable!(),
+				})
+			};
+
+			match chan {
+				Channel::Master => {
+					if let Some(db) = new(vol.master_db())? {
+						vol.set_master_db(db)?;
+					}
+				}
+				Channel::N(chans) => {
+					for &c in chans {
+						if let Some(db) = new(vol.channel_db(c))? {
+							vol.set_channel_db(c, db)?;
+						}
+					}
+				}
+				Channel::All => {
+					for c in 0..vol.chan_count() {
+						if let Some(db) = new(vol.channel_db(c))? {
+							vol.set_channel_db(c, db)?;
+						}
+					}
+				}
+				Channel::Balance | Channel::List(_) => unreachable!(),
+			}
+
+			return Ok(());
+		}
+
+		let val = match val {
+			Value::Db(_) | Value::Balance(_) | Value::Position(_) => unreachable!(),
+			Value::N(n) | Value::Factor(n) => n,
 			Value::MasterChannel => vol.master(),
 			Value::Channel(c) => vol.channel(c),
 		};
 
-		let new = move |old| match self.op {
-			Op::Set => val,
-			Op::Inc => f32::clamp(old + val, 0.0, 1.0),
-			Op::Dec => f32::clamp(old - val, 0.0, 1.0),
+		// A guard that already holds leaves the level alone; the volume keeps the results below the top of the range.
+		let new = move |old: f32| match op {
+			Op::Set => Some(val),
+			Op::Inc => Some(f32::max(old + val, 0.0)),
+			Op::Dec => Some(f32::max(old - val, 0.0)),
+			Op::Mul => Some(f32::max(old * val, 0.0)),
+			Op::Div => Some(f32::max(old / val, 0.0)),
+			Op::Cap => (old > val).then_some(val),
+			Op::Floor => (old < val).then_some(val),
 		};
 
-		match self.chan {
+		match chan {
 			Channel::Master => {
-				let old = vol.master();
-				vol.set_master(new(old));
+				if let Some(x) = new(vol.master()) {
+					vol.set_master(x);
+				}
 			}
-			Channel::N(c) => {
-				let old = vol.channel(c);
-				vol.set_channel(c, new(old));
+			Channel::N(chans) => {
+				for &c in chans {
+					if let Some(x) = new(vol.channel(c)) {
+						vol.set_channel(c, x);
+					}
+				}
 			}
 			Channel::All => {
 				for c in 0..vol.chan_count() {
-					let old = vol.channel(c);
-					vol.set_channel(c, new(old));
+					if let Some(x) = new(vol.channel(c)) {
+						vol.set_channel(c, x);
+					}
 				}
 			}
+			Channel::Balance | Channel::List(_) => unreachable!(),
 		}
+
+		Ok(())
 	}
 }
 
 struct Args {
+	backend: Option<String>,
 	device: Option<String>,
 	id: Option<String>,
+	list: bool,
+	app: Option<String>,
+	list_apps: bool,
+	set_default: Option<String>,
+	flow: Flow,
 	force: bool,
+	fade: Option<Duration>,
+	fade_rate: u32,
+	fade_snap: bool,
+	watch: bool,
+	format: Format,
+	precision: usize,
+	pan_law: PanLaw,
+	save: Option<String>,
+	restore: Option<String>,
+	undo: Option<usize>,
+	history: bool,
+	max: Option<Limit>,
+	floor: Floor,
+	config: Config,
 	dry: bool,
 	quiet: bool,
 	adjusts: Vec<Adjust>,
 }
 
+impl Default for Args {
+	fn default() -> Self {
+		Self {
+			list: false,
+			app: None,
+			list_apps: false,
+			set_default: None,
+			flow: Flow::Render,
+			quiet: false,
+			force: false,
+			fade: None,
+			fade_rate: fade::DEFAULT_RATE,
+			fade_snap: false,
+			watch: false,
+			format: Format::Text,
+			precision: 0,
+			pan_law: PanLaw::default(),
+			save: None,
+			restore: None,
+			undo: None,
+			history: false,
+			max: None,
+			floor: Floor::default(),
+			config: Config::default(),
+			dry: false,
+			id: None,
+			device: None,
+			backend: None,
+			adjusts: Vec::new(),
+		}
+	}
+}
+
+/// The command line settings that the environment and the configuration file have defaults for.
+#[derive(Default)]
+struct Overrides {
+	format: Option<Format>,
+	floor: Option<f32>,
+	floor_policy: Option<FloorPolicy>,
+	force: Option<bool>,
+	quiet: Option<bool>,
+}
+
 fn parse_args() -> Args {
 	let argv = env::args()
 		.skip(1)
@@ -222,14 +969,9 @@ fn parse_args() -> Args {
 		.collect::<Vec<_>>();
 	let mut args = args::preprocess(&argv, "di");
 
-	let mut x = Args {
-		quiet: false,
-		force: false,
-		dry: false,
-		id: None,
-		device: None,
-		adjusts: Vec::new(),
-	};
+	let mut x = Args::default();
+	let mut config = None;
+	let mut cli = Overrides::default();
 
 	while let Some(s) = args.next() {
 		if s == "--" {
@@ -251,31 +993,86 @@ fn parse_args() -> Args {
 				println!("wol {VERSION}");
 				exit(0);
 			}
-			"-l" | "--list" => {
-				for dev in Device::enumerate(DeviceState::ACTIVE | DeviceState::DISABLED)
-					.unwrap_or_else(err_exit)
-				{
-					let name = dev.name();
-					let channels = dev
-						.channels()
-						.map(|n| format!("; {n} Channels"))
-						.unwrap_or_default();
-
-					let id = dev
-						.id()
-						.ok()
-						.filter(|id| !id.is_null())
-						.and_then(|id| unsafe { id.to_string().ok() })
-						.map_or(String::new(), |id| format!("; ID: {id}"));
-
-					println!("{name}: {state}{channels}{id}", state = dev.state());
-				}
-
-				exit(0);
+			"-l" | "--list" => x.list = true,
+			"--list-apps" => x.list_apps = true,
+			"--fade-snap" => x.fade_snap = true,
+			"-w" | "--watch" => x.watch = true,
+			"--format" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --format"));
+				cli.format = Some(Format::parse(&s).unwrap_or_else(err_exit));
+			}
+			"--config" => {
+				config = Some(
+					args.next()
+						.unwrap_or_else(|| err_exit("missing a value for --config")),
+				);
+			}
+			"--precision" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --precision"));
+				x.precision = match s.parse::<usize>() {
+					Ok(n) if n <= 6 => n,
+					_ => err_exit("the value of --precision must be an integer from 0 to 6"),
+				};
+			}
+			"--max" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --max"));
+				x.max = Some(Limit::parse(&s).unwrap_or_else(err_exit));
+			}
+			"--floor" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --floor"));
+				cli.floor = Some(Floor::parse_level(&s).unwrap_or_else(err_exit));
 			}
-			"-f" | "--force" => x.force = true,
+			"--floor-policy" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --floor-policy"));
+				cli.floor_policy = Some(FloorPolicy::parse(&s).unwrap_or_else(|| {
+					err_exit("the value of --floor-policy must be 'screen-reader' or 'always'")
+				}));
+			}
+			"--confirm" => x.floor.confirm = true,
+			"--pan-law" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --pan-law"));
+				x.pan_law = match s.to_lowercase().as_str() {
+					"linear" => PanLaw::Linear,
+					"constant-power" => PanLaw::ConstantPower,
+					_ => err_exit("the value of --pan-law must be 'linear' or 'constant-power'"),
+				};
+			}
+			"--fade" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --fade"));
+				x.fade =
+					Some(fade::parse_duration(&s).unwrap_or_else(|e| {
+						err_exit(format_args!("invalid value for --fade: {e}"))
+					}));
+			}
+			"--fade-rate" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --fade-rate"));
+				x.fade_rate = match s.parse::<u32>() {
+					Ok(n) if n > 0 => n,
+					_ => err_exit("the value of --fade-rate must be a positive integer"),
+				};
+			}
+			"-f" | "--force" => cli.force = Some(true),
+			"--no-force" => cli.force = Some(false),
 			"-n" | "--dry" => x.dry = true,
-			"-q" | "--quiet" => x.quiet = true,
+			"-q" | "--quiet" => cli.quiet = Some(true),
+			"--no-quiet" => cli.quiet = Some(false),
+			"--capture" | "--input" => x.flow = Flow::Capture,
 			"-d" | "--device" => {
 				x.device = Some(
 					args.next()
@@ -283,6 +1080,27 @@ fn parse_args() -> Args {
 						.into(),
 				);
 			}
+			"--app" => {
+				x.app = Some(
+					args.next()
+						.unwrap_or_else(|| err_exit("missing a value for --app"))
+						.into(),
+				)
+			}
+			"--set-default" => {
+				x.set_default = Some(
+					args.next()
+						.unwrap_or_else(|| err_exit("missing a value for --set-default"))
+						.into(),
+				)
+			}
+			"--backend" => {
+				x.backend = Some(
+					args.next()
+						.unwrap_or_else(|| err_exit("missing a value for --backend"))
+						.into(),
+				)
+			}
 			"-i" | "--id" => {
 				x.id = Some(
 					args.next()
@@ -290,6 +1108,29 @@ fn parse_args() -> Args {
 						.into(),
 				)
 			}
+			"--save" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --save"));
+				profile::validate_name(&s).unwrap_or_else(err_exit);
+				x.save = Some(s.into());
+			}
+			"--restore" => {
+				let s = args
+					.next()
+					.unwrap_or_else(|| err_exit("missing a value for --restore"));
+				profile::validate_name(&s).unwrap_or_else(err_exit);
+				x.restore = Some(s.into());
+			}
+			"--undo" => {
+				let n = args.next_if(|s| s.starts_with(|c: char| c.is_ascii_digit()));
+				x.undo = Some(match n.map(|s| s.parse::<usize>()) {
+					None => 1,
+					Some(Ok(n)) if n > 0 => n,
+					Some(_) => err_exit("the value of --undo must be a positive integer"),
+				});
+			}
+			"--history" => x.history = true,
 			_ => {
 				if s.strip_prefix('-')
 					.is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_digit()))
@@ -309,83 +1150,674 @@ fn parse_args() -> Args {
 		}
 	}
 
+	if x.restore.is_some()
+		&& (!x.adjusts.is_empty()
+			|| x.device.is_some()
+			|| x.id.is_some()
+			|| x.app.is_some()
+			|| x.set_default.is_some())
+	{
+		err_exit(
+			"--restore cannot be combined with adjustments, --device, --id, --app or --set-default",
+		)
+	} else if (x.undo.is_some() || x.history)
+		&& (!x.adjusts.is_empty()
+			|| x.device.is_some()
+			|| x.id.is_some()
+			|| x.app.is_some()
+			|| x.set_default.is_some()
+			|| x.save.is_some()
+			|| x.restore.is_some())
+	{
+		err_exit("--undo and --history cannot be combined with adjustments, a device selection, --save or --restore")
+	} else if x.save.is_some() && x.app.is_some() {
+		err_exit("--save cannot be combined with --app")
+	} else if x.save.is_some() && x.dry {
+		err_exit("--save cannot be combined with --dry-run")
+	}
+
+	let config = Config::load(config.as_deref()).unwrap_or_else(err_exit);
+	apply_defaults(&mut x, cli, config, env_var).unwrap_or_else(err_exit);
+
 	x
 }
 
+/// Fill in the settings the command line left out from the environment and then from the configuration file, and resolve the aliases.
+fn apply_defaults(
+	x: &mut Args,
+	cli: Overrides,
+	config: Config,
+	env: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+	let flag = |name: &str| -> Result<Option<bool>, String> {
+		match env(name).map(|s| s.to_lowercase()).as_deref() {
+			None => Ok(None),
+			Some("1" | "true") => Ok(Some(true)),
+			Some("0" | "false") => Ok(Some(false)),
+			Some(_) => Err(format!("the value of {name} must be 1, 0, true or false")),
+		}
+	};
+
+	x.quiet = match cli.quiet {
+		Some(b) => b,
+		None => flag("WOL_QUIET")?.unwrap_or(config.quiet),
+	};
+	x.force = match cli.force {
+		Some(b) => b,
+		None => flag("WOL_FORCE")?.unwrap_or(config.force),
+	};
+	x.floor = Floor {
+		level: cli.floor.or(config.floor).unwrap_or(x.floor.level),
+		policy: cli.floor_policy.or(config.floor_policy).unwrap_or_default(),
+		confirm: x.floor.confirm || config.confirm,
+	};
+	x.format = match (cli.format, env("WOL_FORMAT"), &config.format) {
+		(Some(f), _, _) => f,
+		(None, Some(s), _) => Format::parse(&s).map_err(|e| format!("in WOL_FORMAT: {e}"))?,
+		(None, None, Some(s)) => {
+			Format::parse(s).map_err(|e| format!("in the configuration file: {e}"))?
+		}
+		(None, None, None) => Format::Text,
+	};
+	if x.backend.is_none() && env("WOL_BACKEND").is_none() {
+		x.backend = config.backend.clone();
+	}
+	// --restore, --undo and --history find their devices by themselves.
+	if x.device.is_none() && x.id.is_none() && x.restore.is_none() && x.undo.is_none() && !x.history
+	{
+		(x.device, x.id) = match (env("WOL_DEVICE"), env("WOL_ID")) {
+			(None, None) => (config.device.clone(), config.id.clone()),
+			env => env,
+		};
+	}
+
+	// Aliases stand for a device ID or name wherever a device name is accepted.
+	if let Some(alias) = x.device.as_deref().and_then(|s| config.alias(s)) {
+		match alias {
+			Alias::Id(id) => {
+				x.id = Some(id.clone());
+				x.device = None;
+			}
+			Alias::Name(name) => x.device = Some(name.clone()),
+		}
+	}
+	if let Some(alias) = x.set_default.as_deref().and_then(|s| config.alias(s)) {
+		x.set_default = Some(match alias {
+			Alias::Id(s) | Alias::Name(s) => s.clone(),
+		});
+	}
+	x.config = config;
+
+	Ok(())
+}
+
+fn list_devices(backend: &dyn Backend, flow: Flow, format: &Format) -> Result<()> {
+	let defaults = Role::ALL.map(|role| {
+		(
+			role,
+			Device::default_id(backend, flow, role).unwrap_or_default(),
+		)
+	});
+	let mut list = Vec::new();
+
+	for dev in Device::enumerate(backend, flow, DeviceState::ACTIVE | DeviceState::DISABLED)? {
+		let name = dev.name();
+		let channels = dev
+			.channels()
+			.map(|n| format!("; {n} Channels"))
+			.unwrap_or_default();
+
+		let id = dev.id().ok().filter(|id| !id.is_empty());
+
+		let roles = defaults
+			.iter()
+			.filter(|(_, default)| id.as_ref() == Some(default))
+			.map(|&(role, _)| role)
+			.collect::<Vec<_>>();
+
+		if matches!(format, Format::Json) {
+			list.push(json::device(&dev, &roles));
+			continue;
+		}
+
+		let default = match roles.len() {
+			0 => String::new(),
+			n if n == Role::ALL.len() => String::from("; Default"),
+			_ => format!(
+				"; Default for {}",
+				roles
+					.iter()
+					.map(Role::to_string)
+					.collect::<Vec<_>>()
+					.join(", ")
+			),
+		};
+
+		let id = id.map_or(String::new(), |id| format!("; ID: {id}"));
+
+		let muted = if dev.is_muted().unwrap_or(false) {
+			"; Muted"
+		} else {
+			""
+		};
+
+		println!(
+			"{name}: {state}{channels}{default}{muted}{id}",
+			state = dev.state()
+		);
+	}
+
+	if matches!(format, Format::Json) {
+		println!("{}", serde_json::Value::Array(list));
+	}
+
+	Ok(())
+}
+
+fn list_sessions(dev: &Device, format: &Format, precision: usize) -> Result<()> {
+	let sessions = dev.sessions()?;
+	if matches!(format, Format::Json) {
+		println!(
+			"{}",
+			serde_json::Value::Array(sessions.iter().map(json::session).collect())
+		);
+		return Ok(());
+	}
+
+	for s in sessions {
+		let process = match s.process() {
+			"" => "Unknown",
+			name => name,
+		};
+		let volume = s
+			.control()
+			.master_volume()
+			.map(|n| format!("; Volume: {:.precision$}", n * 100.0))
+			.unwrap_or_default();
+		let muted = if s.control().is_muted().unwrap_or(false) {
+			"; Muted"
+		} else {
+			""
+		};
+
+		println!(
+			"{process}: {state}; PID: {pid}{volume}{muted}",
+			state = s.state(),
+			pid = s.pid()
+		);
+	}
+
+	Ok(())
+}
+
+fn show_history(format: &Format, precision: usize) -> Result<()> {
+	let history = History::load()?;
+	let entries = history.entries();
+	if matches!(format, Format::Json) {
+		println!(
+			"{}",
+			serde_json::Value::Array(entries.iter().map(history::Entry::to_json).collect())
+		);
+		return Ok(());
+	}
+
+	for (i, e) in entries.iter().enumerate() {
+		let mute = match (e.before.muted, e.after.muted) {
+			(false, true) => "; Muted",
+			(true, false) => "; Unmuted",
+			_ => "",
+		};
+		println!(
+			"{n}: {time} UTC; {name}; Master: {before:.precision$} -> {after:.precision$}{mute}",
+			n = entries.len() - i,
+			time = e.time_utc(),
+			name = e.name,
+			before = e.before.master * 100.0,
+			after = e.after.master * 100.0,
+		);
+	}
+
+	Ok(())
+}
+
+/// Restore the levels from before the last `n` changes in the history and remove them from it.
+///
+/// A device changed several times goes back to its levels before the earliest of the changes.
+fn undo(backend: &dyn Backend, args: &Args, n: usize) -> Result<()> {
+	let mut history = History::load()?;
+	let entries = history.pop(n)?;
+	let targets = undo_targets(&entries);
+
+	let mut vols = Vec::with_capacity(targets.len());
+	for e in targets {
+		let dev = history_device(backend, e)?;
+		let max = args.max.or_else(|| args.config.max_for(&dev));
+		let mut vol = Volume::new(dev)?;
+		vol.set_pan_law(args.pan_law);
+		vol.set_floor(args.floor);
+		if vol.chan_count() as usize != e.before.channels.len() {
+			return Err(format!(
+				"{} had {} channels but now has {}",
+				e.name,
+				e.before.channels.len(),
+				vol.chan_count()
+			)
+			.into());
+		}
+
+		e.before.apply(&mut vol);
+		if let Some(max) = max {
+			vol.set_max(max);
+			enforce_max(&mut vol, max, args)?;
+		}
+		vols.push((e.id.clone(), e.flow, vol));
+	}
+
+	if !args.dry {
+		// Check every device before reverting any, so a refusal leaves all of them alone.
+		for (_, _, vol) in &vols {
+			vol.check(args.force)?;
+		}
+		for (i, (_, _, vol)) in vols.iter().enumerate() {
+			if let Err(err) = vol.commit(true) {
+				// Keep the changes of the devices that were not reverted in the history.
+				let reverted = &vols[..i];
+				for e in entries.into_iter().rev().filter(|e| {
+					!reverted
+						.iter()
+						.any(|(id, flow, _)| *id == e.id && *flow == e.flow)
+				}) {
+					history.push(e);
+				}
+				history.save()?;
+				return Err(err);
+			}
+		}
+		history.save()?;
+	}
+
+	if !args.quiet {
+		if matches!(args.format, Format::Text) {
+			match (args.dry, n) {
+				(false, 1) => println!("undid the last change"),
+				(false, n) => println!("undid the last {n} changes"),
+				(true, 1) => println!("would undo the last change"),
+				(true, n) => println!("would undo the last {n} changes"),
+			}
+		}
+		for (_, _, vol) in &vols {
+			print!(
+				"{}",
+				status(vol, args.dry, None, &args.format, args.precision)
+			);
+		}
+	}
+
+	Ok(())
+}
+
+/// The oldest of the given entries for each device, whose levels before the change are the ones to go back to.
+///
+/// The entries are newest first, as returned by [History::pop].
+fn undo_targets(entries: &[history::Entry]) -> Vec<&history::Entry> {
+	let mut targets = Vec::<&history::Entry>::new();
+	for e in entries {
+		match targets
+			.iter_mut()
+			.find(|t| t.id == e.id && t.flow == e.flow)
+		{
+			Some(t) => *t = e,
+			None => targets.push(e),
+		}
+	}
+	targets
+}
+
+/// Find the device a profile was saved from.
+fn profile_device(backend: &dyn Backend, name: &str, profile: &Profile) -> Result<Device> {
+	find_by_id(backend, profile.flow, &profile.id)?.ok_or_else(|| {
+		format!(
+			"the {} device of profile '{name}' is not connected (ID {})",
+			profile.flow, profile.id
+		)
+		.into()
+	})
+}
+
+/// Find the device of a history entry.
+fn history_device(backend: &dyn Backend, e: &history::Entry) -> Result<Device> {
+	find_by_id(backend, e.flow, &e.id)?.ok_or_else(|| {
+		format!(
+			"the {} device {} is not connected (ID {})",
+			e.flow, e.name, e.id
+		)
+		.into()
+	})
+}
+
+/// Lower the levels to the maximum of the device or refuse them, depending on the configuration; --force lifts the maximum.
+fn enforce_max(vol: &mut Volume, max: Limit, args: &Args) -> Result<()> {
+	if args.force || !vol.limit(false)?.any() {
+		return Ok(());
+	}
+
+	match args.config.max_action {
+		LimitAction::Clamp => {
+			let limited = vol.limit(true)?;
+			let name = vol.device().name();
+			if limited.lowered {
+				eprintln!("warning: lowered the levels of {name} to its maximum of {max}");
+			}
+			if limited.held {
+				eprintln!(
+					"warning: kept the channels of {name} that were already above its maximum of {max} at their current levels"
+				);
+			}
+			Ok(())
+		}
+		LimitAction::Refuse => Err(Error::Refused(
+			format!(
+				"refusing to set the levels of {} above its maximum of {max}\nhint: use --force to override this behaviour",
+				vol.device().name()
+			)
+			.into(),
+		)),
+	}
+}
+
+fn find_session(dev: &Device, app: &str) -> Result<Session> {
+	let mut sessions = dev
+		.sessions()?
+		.into_iter()
+		.filter(|s| s.matches(app))
+		.collect::<Vec<_>>();
+
+	match &*sessions {
+		[_] => Ok(sessions.pop().unwrap()),
+		[] => Err(format!("no application named '{app}' is using {}", dev.name()).into()),
+		_ => {
+			eprintln!("error: ambiguous application name '{app}'; multiple sessions found:");
+			for s in &sessions {
+				eprintln!("{} (PID {})", s.process(), s.pid());
+			}
+			eprintln!("hint: specify the process ID instead");
+			exit(1);
+		}
+	}
+}
+
+fn find_by_id(backend: &dyn Backend, flow: Flow, id: &str) -> Result<Option<Device>> {
+	Ok(
+		Device::enumerate(backend, flow, DeviceState::ACTIVE | DeviceState::DISABLED)?
+			.find(|dev| dev.id().is_ok_and(|s| s.eq_ignore_ascii_case(id))),
+	)
+}
+
+fn find_by_name(backend: &dyn Backend, flow: Flow, name: &str) -> Result<Device> {
+	let s = name.to_uppercase();
+
+	let mut devices =
+		Device::enumerate(backend, flow, DeviceState::ACTIVE | DeviceState::DISABLED)?
+			.filter(|d| d.name().to_uppercase().contains(&s))
+			.collect::<Vec<_>>();
+
+	match &*devices {
+		[_] => Ok(devices.pop().unwrap()),
+		[] => err_exit(format_args!("no such device: {}", name)),
+		_ => {
+			eprintln!("error: ambiguous device name '{name}'; multiple matches found:");
+			for dev in &devices {
+				eprintln!("{}", dev.name());
+			}
+			exit(1);
+		}
+	}
+}
+
+/// Get the device selected with --device or --id, or the default device.
+fn select_device(backend: &dyn Backend, args: &Args) -> Result<Device> {
+	match (&args.device, &args.id) {
+		(None, None) => Device::get_default(backend, args.flow),
+		(Some(name), None) => find_by_name(backend, args.flow, name),
+		(_, Some(id)) => find_by_id(backend, args.flow, id)?.ok_or_else(|| {
+			format!("no active {} device found with the provided ID", args.flow).into()
+		}),
+	}
+}
+
 fn run() -> Result<()> {
 	let args = parse_args();
+	let backend = backend::new(args.backend.as_deref())?;
+	let backend = &*backend;
 
-	let dev = match (&args.device, &args.id) {
-		(None, None) => Device::get_default()?,
-		(Some(name), None) => {
-			let s = name.to_uppercase();
+	if args.list {
+		return list_devices(backend, args.flow, &args.format);
+	} else if args.history {
+		return show_history(&args.format, args.precision);
+	} else if let Some(n) = args.undo {
+		return undo(backend, &args, n);
+	}
 
-			let mut devices = Device::enumerate(DeviceState::ACTIVE | DeviceState::DISABLED)?
-				.filter(|d| d.name().to_uppercase().contains(&s))
-				.collect::<Vec<_>>();
+	let profile = match &args.restore {
+		Some(name) => Some((name, Profile::load(name)?)),
+		None => None,
+	};
 
-			match &*devices {
-				[_] => devices.pop().unwrap(),
-				[] => err_exit(format_args!("no such device: {}", name)),
-				_ => {
-					eprintln!("error: ambiguous device name '{name}'; multiple matches found:");
-					for dev in &devices {
-						eprintln!("{}", dev.name());
-					}
-					exit(1);
+	// A profile brings its own device, which may be of the other flow.
+	let flow = profile.as_ref().map_or(args.flow, |(_, p)| p.flow);
+	let dev = match (&profile, &args.set_default) {
+		(Some((name, profile)), _) => profile_device(backend, name, profile)?,
+		(None, Some(s)) => {
+			// IDs are tried first since a name only has to match as a substring.
+			let dev = match find_by_id(backend, args.flow, s)? {
+				Some(dev) => dev,
+				None => find_by_name(backend, args.flow, s)?,
+			};
+			if !args.dry {
+				dev.set_default()?;
+			}
+			if !args.quiet && matches!(args.format, Format::Text) {
+				match args.dry {
+					false => println!("{} is now the default {} device", dev.name(), args.flow),
+					true => println!("would make {} the default {} device", dev.name(), args.flow),
 				}
 			}
+			dev
 		}
-		(_, Some(id)) => Device::enumerate(DeviceState::ACTIVE | DeviceState::DISABLED)?
-			.find(|dev| {
-				dev.id()
-					.ok()
-					.filter(|s| !s.is_null())
-					.and_then(|s| unsafe { s.to_string() }.ok())
-					.is_some_and(|s| s.eq_ignore_ascii_case(id))
-			})
-			.ok_or("no active device found with the provided ID")?,
+		(None, None) => select_device(backend, &args)?,
+	};
+
+	if args.list_apps {
+		return list_sessions(&dev, &args.format, args.precision);
+	}
+
+	// Application sessions are relative to their device, so only an explicit --max applies to them.
+	let max = match &args.app {
+		Some(_) => args.max,
+		None => args.max.or_else(|| args.config.max_for(&dev)),
+	};
+	let dev = match &args.app {
+		Some(app) => find_session(&dev, app)?.into_control(),
+		None => dev,
 	};
 
 	let mut vol = Volume::new(dev)?;
+	vol.set_pan_law(args.pan_law);
+	// Quieting one application leaves the device and the screen reader audible.
+	vol.set_floor(match &args.app {
+		Some(_) => Floor {
+			level: 0.0,
+			..args.floor
+		},
+		None => args.floor,
+	});
+	if let Some(max) = max {
+		vol.set_max(max);
+	}
 	let chan_count = vol.chan_count();
 
 	for a in &args.adjusts {
-		if let Channel::N(c) = a.chan {
-			if c >= chan_count {
-				return Err(format!("the device only has {chan_count} channels").into());
+		a.validate(chan_count)?;
+	}
+	let adjusts = args
+		.adjusts
+		.iter()
+		.map(|a| a.resolve(&vol))
+		.collect::<Result<Vec<_>>>()?;
+
+	// How long each channel takes to reach its new level; the last adjustment to change a channel decides.
+	let mut durations = vec![Duration::ZERO; chan_count as usize];
+	// Whether any guard changed the levels, if there are guards.
+	let mut guarded = None;
+	for a in &adjusts {
+		let old = vol.channels().to_vec();
+		a.apply(&mut vol)?;
+		if a.is_guard() {
+			*guarded.get_or_insert(false) |= old != vol.channels();
+		}
+
+		let fade = match a {
+			Adjust::Level { fade, .. }
+			| Adjust::Swap { fade, .. }
+			| Adjust::Mirror { fade, .. } => fade.or(args.fade).unwrap_or_default(),
+			Adjust::Mute(_) => continue,
+		};
+		for (c, (&old, &new)) in old.iter().zip(vol.channels()).enumerate() {
+			if old != new {
+				durations[c] = fade;
 			}
 		}
 	}
 
-	for a in &args.adjusts {
-		a.apply(&mut vol);
+	if let Some((name, profile)) = &profile {
+		if profile.levels.channels.len() != chan_count as usize {
+			return Err(format!(
+				"profile '{name}' has {} channels but the device has {chan_count}",
+				profile.levels.channels.len()
+			)
+			.into());
+		}
+
+		let old = vol.channels().to_vec();
+		profile.levels.apply(&mut vol);
+		for (c, (&old, &new)) in old.iter().zip(vol.channels()).enumerate() {
+			if old != new {
+				durations[c] = args.fade.unwrap_or_default();
+			}
+		}
 	}
 
-	if !args.dry && !args.adjusts.is_empty() {
-		vol.commit(args.force)?;
+	if let Some(max) = max {
+		enforce_max(&mut vol, max, &args)?;
 	}
 
-	if !args.quiet && args.dry {
-		println!("master: {:.0}", vol.master() * 100.0);
+	if !args.dry && (!args.adjusts.is_empty() || profile.is_some()) {
+		if durations.iter().any(|d| !d.is_zero()) {
+			fade::run(
+				&mut vol,
+				&durations,
+				args.fade_rate,
+				args.fade_snap,
+				args.force,
+			)?;
+		} else {
+			vol.commit(args.force)?;
+		}
+
+		let mut entry = match &args.app {
+			None => history::Entry::new(&vol, flow),
+			// Sessions are identified by their process rather than a device ID, so they are left out.
+			Some(_) => Ok(None),
+		};
+
+		// The device may round levels set in decibels differently than the estimates.
+		if vol.has_db() {
+			vol = Volume::new(vol.into_device())?;
+			vol.set_pan_law(args.pan_law);
+			if let Ok(Some(e)) = &mut entry {
+				e.after = Levels::pending(&vol);
+			}
+		}
+
+		// The changes are applied by now, so failing to record them is not an error.
+		if let Err(e) = entry.and_then(|e| e.map_or(Ok(()), history::record)) {
+			eprintln!("warning: failed to record the change in the history: {e}");
+		}
+	}
+
+	if let Some(name) = &args.save {
+		Profile::new(&vol, flow)?.save(name)?;
+		if !args.quiet && matches!(args.format, Format::Text) {
+			println!(
+				"saved the levels of {} to profile '{name}'",
+				vol.device().name()
+			);
+		}
+	}
+
+	if args.watch {
+		return watch(backend, &args);
+	}
+
+	if !args.quiet {
+		print!(
+			"{}",
+			status(&vol, args.dry, guarded, &args.format, args.precision)
+		);
+	}
+
+	Ok(())
+}
+
+/// Format the levels of `vol`; the dry run format leaves out the decibels since the changes are not applied.
+///
+/// If guards were given, `guarded` tells whether any of them changed the levels.
+fn status(
+	vol: &Volume,
+	dry: bool,
+	guarded: Option<bool>,
+	format: &Format,
+	precision: usize,
+) -> String {
+	match format {
+		Format::Text => (),
+		Format::Json => return format!("{}\n", json::status(vol, dry, guarded)),
+		Format::Template(t) => return format!("{}\n", t.render(vol, dry, precision)),
+	}
+
+	let mut out = String::new();
+	let chan_count = vol.chan_count();
+	let muted = if vol.is_muted() { " (muted)" } else { "" };
+	let label = |c: usize| match vol.position(c as u32) {
+		Some(pos) => format!("ch{c} ({pos})"),
+		None => format!("ch{c}"),
+	};
+
+	if dry {
+		let _ = writeln!(out, "master: {:.precision$}{muted}", vol.master() * 100.0);
 
 		match chan_count {
 			0 | 1 => (),
 			2 => {
-				println!(
-					"balance: {:.0}/{:.0}",
+				let _ = writeln!(
+					out,
+					"balance: {:.precision$}/{:.precision$}",
 					vol.channel(0) * 100.0,
 					vol.channel(1) * 100.0
 				);
 			}
 			_ => {
 				for (c, &val) in vol.channels().iter().enumerate() {
-					println!("ch{}: {:.0}", c, val * 100.0);
+					let _ = writeln!(out, "{}: {:.precision$}", label(c), val * 100.0);
 				}
 			}
 		}
-	} else if !args.quiet {
-		let db = |f| {
+	} else {
+		// Not every backend can report decibels for every device.
+		let db = |f: Result<f32>| {
+			let Ok(f) = f else {
+				return String::from("n/a");
+			};
 			let mut s = format!("{f:.2}");
 			while s.ends_with('0') {
 				s.pop();
@@ -397,42 +1829,437 @@ fn run() -> Result<()> {
 			s
 		};
 
-		println!(
-			"master: {:.0} [{}]",
+		let _ = writeln!(
+			out,
+			"master: {:.precision$} [{}]{muted}",
 			vol.master() * 100.0,
-			db(vol.master_active_db()?)
+			db(vol.master_active_db())
 		);
 
 		match chan_count {
 			0 | 1 => (),
 			2 => {
-				println!(
-					"balance: {:.0}/{:.0} [{}/{}]",
+				let _ = writeln!(
+					out,
+					"balance: {:.precision$}/{:.precision$} [{}/{}]",
 					vol.channel(0) * 100.0,
 					vol.channel(1) * 100.0,
-					db(vol.channel_active_db(0)?),
-					db(vol.channel_active_db(1)?),
+					db(vol.channel_active_db(0)),
+					db(vol.channel_active_db(1)),
 				);
 			}
 			_ => {
 				for (c, &val) in vol.channels().iter().enumerate() {
-					println!(
-						"ch{}: {:.0} [{}]",
-						c,
+					let _ = writeln!(
+						out,
+						"{}: {:.precision$} [{}]",
+						label(c),
 						val * 100.0,
-						db(vol.channel_active_db(c as u32)?)
+						db(vol.channel_active_db(c as u32))
 					);
 				}
 			}
 		}
 	}
 
-	Ok(())
+	match guarded {
+		Some(true) => out.push_str("guards: changed the levels\n"),
+		Some(false) => out.push_str("guards: no change\n"),
+		None => (),
+	}
+
+	out
+}
+
+/// Print the status every time it changes, until the notifications stop.
+///
+/// Unless a device is pinned with --device or --id, the device is looked up again on every change so that the default device is followed.
+fn watch(backend: &dyn Backend, args: &Args) -> Result<()> {
+	let (tx, rx) = mpsc::channel();
+	let _devices = Device::subscribe_all(backend, args.flow, tx.clone())?;
+	let mut subscription = None;
+	let mut last_id = None;
+	let mut last = String::new();
+
+	loop {
+		let dev = select_device(backend, args)?;
+		let id = dev.id().ok();
+		if subscription.is_none() || id != last_id {
+			// Unregister the old device's callback before registering the new one.
+			drop(subscription.take());
+			subscription = Some(dev.subscribe(tx.clone())?);
+			last_id = id;
+		}
+
+		let dev = match &args.app {
+			Some(app) => find_session(&dev, app)?.into_control(),
+			None => dev,
+		};
+		let mut vol = Volume::new(dev)?;
+		vol.set_pan_law(args.pan_law);
+		let s = status(&vol, false, None, &args.format, args.precision);
+		if s != last {
+			print!("{s}");
+			last = s;
+		}
+
+		rx.recv()
+			.map_err(|_| "stopped receiving change notifications")??;
+		// Changes tend to come in bursts.
+		thread::sleep(WATCH_DEBOUNCE);
+		while let Ok(x) = rx.try_recv() {
+			x?;
+		}
+	}
 }
 
 fn main() {
 	if let Err(e) = run() {
 		eprintln!("error: {e}");
-		exit(1);
+		exit(e.exit_code());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::memory::Memory;
+
+	fn volume(backend: &Memory) -> Volume {
+		Volume::new(backend.default_device(Flow::Render).unwrap()).unwrap()
+	}
+
+	/// Parse an adjustment and apply it to the pending levels.
+	fn adjust(vol: &mut Volume, s: &str) -> Result<()> {
+		let a = Adjust::parse(s)?;
+		a.validate(vol.chan_count())?;
+		a.resolve(vol)?.apply(vol)
+	}
+
+	#[test]
+	fn parse_range() {
+		let a = Adjust::parse("4-10=40").unwrap();
+		assert!(matches!(
+			a,
+			Adjust::Level {
+				op: Op::Set,
+				chan: Channel::List(ref chans),
+				val: Value::N(n),
+				fade: None,
+			} if matches!(chans[..], [Chan::Range(4, 10)]) && n == 0.4
+		));
+		assert_eq!(
+			Adjust::parse("1-0=3").err(),
+			Some("the start of a channel range must not be greater than its end")
+		);
+		assert_eq!(Adjust::parse("l=-5").err(), Adjust::parse("l=5x").err());
+		assert_eq!(Adjust::parse("4-10=x").err(), Adjust::parse("l=x").err());
+	}
+
+	#[test]
+	fn parse_guard() {
+		let a = Adjust::parse("m<=30").unwrap();
+		assert!(a.is_guard());
+		assert!(matches!(
+			a,
+			Adjust::Level {
+				op: Op::Cap,
+				chan: Channel::Master,
+				val: Value::N(n),
+				..
+			} if n == 0.3
+		));
+		assert!(matches!(
+			Adjust::parse("a>=20").unwrap(),
+			Adjust::Level {
+				op: Op::Floor,
+				chan: Channel::All,
+				..
+			}
+		));
+	}
+
+	#[test]
+	fn parse_swap() {
+		assert!(matches!(
+			Adjust::parse("l<>r").unwrap(),
+			Adjust::Swap {
+				a: Channel::List(ref a),
+				b: Channel::List(ref b),
+				fade: None,
+			} if matches!((&a[..], &b[..]), ([Chan::N(0)], [Chan::N(1)]))
+		));
+		assert!(Adjust::parse("m<>r").is_err());
+	}
+
+	#[test]
+	fn parse_mirror() {
+		assert!(matches!(
+			Adjust::parse("rear=front").unwrap(),
+			Adjust::Mirror {
+				to: Channel::List(ref to),
+				from: Channel::List(ref from),
+				fade: None,
+			} if matches!((&to[..], &from[..]), ([Chan::Group(Group::Rear)], [Chan::Group(Group::Front)]))
+		));
+		// A single channel on the right copies its level instead.
+		assert!(matches!(
+			Adjust::parse("rear=fl").unwrap(),
+			Adjust::Level {
+				op: Op::Set,
+				val: Value::Position(Position::FrontLeft),
+				..
+			}
+		));
+	}
+
+	#[test]
+	fn parse_db() {
+		assert!(matches!(
+			Adjust::parse("-12db").unwrap(),
+			Adjust::Level {
+				op: Op::Dec,
+				chan: Channel::Master,
+				val: Value::Db(db),
+				..
+			} if db == 12.0
+		));
+		assert!(matches!(
+			Adjust::parse("l=-1.5dB").unwrap(),
+			Adjust::Level {
+				op: Op::Set,
+				val: Value::Db(db),
+				..
+			} if db == -1.5
+		));
+	}
+
+	#[test]
+	fn parse_fade() {
+		assert!(matches!(
+			Adjust::parse("50~2s").unwrap(),
+			Adjust::Level {
+				op: Op::Set,
+				val: Value::N(n),
+				fade: Some(d),
+				..
+			} if n == 0.5 && d == Duration::from_secs(2)
+		));
+		assert!(Adjust::parse("mute~2s").is_err());
+	}
+
+	#[test]
+	fn parse_values() {
+		let level = |s| match Adjust::parse(s) {
+			Ok(Adjust::Level {
+				val: Value::N(n), ..
+			}) => Some(n),
+			_ => None,
+		};
+		assert_eq!(level("33.5"), Some(0.335));
+		assert_eq!(level("0.5%"), Some(0.005));
+		assert_eq!(level("l-0.5"), Some(0.005));
+		assert_eq!(level("0.25f"), Some(0.25));
+		assert_eq!(level("2f"), None);
+		assert_eq!(level("101"), None);
+	}
+
+	#[test]
+	fn apply() {
+		let backend = Memory::new("Surround=10,20,30,40").unwrap();
+		let mut vol = volume(&backend);
+		adjust(&mut vol, "l<>r").unwrap();
+		assert_eq!(vol.channels(), [0.2, 0.1, 0.3, 0.4]);
+		adjust(&mut vol, "rear=front").unwrap();
+		assert_eq!(vol.channels(), [0.2, 0.1, 0.1, 0.2]);
+		adjust(&mut vol, "m<=15").unwrap();
+		assert_eq!(vol.master(), 0.15);
+		adjust(&mut vol, "4-10=40").unwrap_err();
+	}
+
+	#[test]
+	fn restore_capture_then_undo() {
+		let backend = Memory::new("Speakers=50,50;Mic:capture=30,30").unwrap();
+		let mic = Volume::new(backend.default_device(Flow::Capture).unwrap()).unwrap();
+		let profile = Profile::new(&mic, Flow::Capture).unwrap();
+		let mut mic = Volume::new(mic.into_device()).unwrap();
+		mic.set_master(0.8);
+		mic.commit(true).unwrap();
+
+		// Restoring without --capture still finds and records the input device.
+		let dev = profile_device(&backend, "mic", &profile).unwrap();
+		let mut vol = Volume::new(dev).unwrap();
+		profile.levels.apply(&mut vol);
+		vol.commit(true).unwrap();
+		let entry = history::Entry::new(&vol, profile.flow).unwrap().unwrap();
+		assert_eq!(entry.flow, Flow::Capture);
+
+		let mut vol = Volume::new(history_device(&backend, &entry).unwrap()).unwrap();
+		assert_eq!(vol.device().name(), "Mic");
+		assert_eq!(vol.channels(), [0.3, 0.3]);
+		entry.before.apply(&mut vol);
+		assert_eq!(vol.channels(), [0.8, 0.8]);
+	}
+
+	/// Resolve the defaults for `args` from the given overrides, configuration
+	/// and environment.
+	fn defaults(
+		mut args: Args,
+		cli: Overrides,
+		config: &str,
+		env: &[(&str, &str)],
+	) -> Result<Args, String> {
+		let config = Config::parse(config).unwrap();
+		let env = |name: &str| {
+			env.iter()
+				.find(|(k, _)| *k == name)
+				.map(|(_, v)| v.to_string())
+		};
+		apply_defaults(&mut args, cli, config, env)?;
+		Ok(args)
+	}
+
+	#[test]
+	fn precedence() {
+		let config = "quiet = true\nforce = true\nformat = \"json\"\ndevice = \"Speakers\"";
+
+		let x = defaults(Args::default(), Overrides::default(), config, &[]).unwrap();
+		assert!(x.quiet && x.force);
+		assert!(matches!(x.format, Format::Json));
+		assert_eq!(x.device.as_deref(), Some("Speakers"));
+
+		let env = [
+			("WOL_QUIET", "0"),
+			("WOL_FORCE", "FALSE"),
+			("WOL_FORMAT", "text"),
+			("WOL_DEVICE", "Headset"),
+		];
+		let x = defaults(Args::default(), Overrides::default(), config, &env).unwrap();
+		assert!(!x.quiet && !x.force);
+		assert!(matches!(x.format, Format::Text));
+		assert_eq!(x.device.as_deref(), Some("Headset"));
+
+		let cli = Overrides {
+			format: Some(Format::Json),
+			force: Some(true),
+			quiet: Some(true),
+			..Overrides::default()
+		};
+		let args = Args {
+			device: Some(String::from("Monitor")),
+			..Args::default()
+		};
+		let x = defaults(args, cli, config, &env).unwrap();
+		assert!(x.quiet && x.force);
+		assert!(matches!(x.format, Format::Json));
+		assert_eq!(x.device.as_deref(), Some("Monitor"));
+
+		// An ID in the environment replaces the configured device too.
+		let x = defaults(
+			Args::default(),
+			Overrides::default(),
+			config,
+			&[("WOL_ID", "x")],
+		)
+		.unwrap();
+		assert_eq!((x.device, x.id.as_deref()), (None, Some("x")));
+
+		// Restoring a profile doesn't use the default device.
+		let args = Args {
+			restore: Some(String::from("quiet")),
+			..Args::default()
+		};
+		let x = defaults(args, Overrides::default(), config, &env).unwrap();
+		assert_eq!((x.device, x.id), (None, None));
+	}
+
+	#[test]
+	fn aliases() {
+		let config =
+			"device = \"tv\"\n[aliases]\nhp = { id = \"memory:1\" }\ntv = { name = \"HDMI\" }";
+
+		let x = defaults(Args::default(), Overrides::default(), config, &[]).unwrap();
+		assert_eq!((x.device.as_deref(), x.id), (Some("HDMI"), None));
+
+		let args = Args {
+			device: Some(String::from("HP")),
+			set_default: Some(String::from("tv")),
+			..Args::default()
+		};
+		let x = defaults(args, Overrides::default(), config, &[]).unwrap();
+		assert_eq!((x.device, x.id.as_deref()), (None, Some("memory:1")));
+		assert_eq!(x.set_default.as_deref(), Some("HDMI"));
+
+		let x = defaults(
+			Args::default(),
+			Overrides::default(),
+			config,
+			&[("WOL_DEVICE", "hp")],
+		)
+		.unwrap();
+		assert_eq!((x.device, x.id.as_deref()), (None, Some("memory:1")));
+	}
+
+	#[test]
+	fn invalid_env() {
+		let err = |env| {
+			defaults(Args::default(), Overrides::default(), "", &[env])
+				.err()
+				.unwrap()
+		};
+		assert_eq!(
+			err(("WOL_QUIET", "yes")),
+			"the value of WOL_QUIET must be 1, 0, true or false"
+		);
+		assert_eq!(
+			err(("WOL_FORCE", "2")),
+			"the value of WOL_FORCE must be 1, 0, true or false"
+		);
+		assert!(err(("WOL_FORMAT", "{")).starts_with("in WOL_FORMAT: "));
+
+		// Flags given on the command line don't look at the environment.
+		let cli = Overrides {
+			quiet: Some(false),
+			..Overrides::default()
+		};
+		assert!(defaults(Args::default(), cli, "", &[("WOL_QUIET", "yes")]).is_ok());
+		assert!(
+			defaults(Args::default(), Overrides::default(), "format = \"{\"", &[])
+				.err()
+				.unwrap()
+				.starts_with("in the configuration file: ")
+		);
+	}
+
+	#[test]
+	fn undo_last_changes() {
+		let backend = Memory::new("Speakers=50,50;Mic:capture=30,30").unwrap();
+		let mut history = History::default();
+		let mut change = |flow, level| {
+			let mut vol = Volume::new(backend.default_device(flow).unwrap()).unwrap();
+			vol.set_master(level);
+			vol.commit(true).unwrap();
+			history.push(history::Entry::new(&vol, flow).unwrap().unwrap());
+		};
+		change(Flow::Render, 0.6);
+		change(Flow::Capture, 0.4);
+		change(Flow::Render, 0.7);
+		change(Flow::Render, 0.8);
+
+		// Undoing the last 3 changes goes back to the levels before the oldest of them on each device.
+		let entries = history.pop(3).unwrap();
+		let targets = undo_targets(&entries);
+		assert_eq!(targets.len(), 2);
+		assert_eq!(
+			(targets[0].flow, targets[0].before.master),
+			(Flow::Render, 0.6)
+		);
+		assert_eq!(
+			(targets[1].flow, targets[1].before.master),
+			(Flow::Capture, 0.3)
+		);
+		assert_eq!(history.entries().len(), 1);
+		assert!(history.pop(2).is_err());
+		assert_eq!(history.entries().len(), 1);
 	}
 }
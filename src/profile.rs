@@ -0,0 +1,165 @@
+use std::{
+	fs,
+	path::PathBuf,
+};
+
+use serde_json::{
+	json,
+	Value as Json,
+};
+
+use crate::{
+	device::Flow,
+	dirs,
+	error::Result,
+	json,
+	volume::Volume,
+};
+
+/// The levels and the mute state of a device.
+#[derive(Clone, PartialEq)]
+pub struct Levels {
+	pub master: f32,
+	pub channels: Vec<f32>,
+	pub muted: bool,
+}
+
+/// A snapshot of the levels of a device, saved with --save and applied with --restore.
+pub struct Profile {
+	pub id: String,
+	pub flow: Flow,
+	pub levels: Levels,
+}
+
+impl Levels {
+	/// The pending levels of `vol`.
+	pub fn pending(vol: &Volume) -> Self {
+		Self {
+			master: vol.master(),
+			channels: vol.channels().to_vec(),
+			muted: vol.is_muted(),
+		}
+	}
+
+	/// The levels of `vol` before any adjustment.
+	pub fn initial(vol: &Volume) -> Self {
+		Self {
+			master: vol.init_master(),
+			channels: vol.init_channels().to_vec(),
+			muted: vol.init_muted(),
+		}
+	}
+
+	/// Make these the pending levels of `vol`, which must have as many channels.
+	pub fn apply(&self, vol: &mut Volume) {
+		for (c, &level) in self.channels.iter().enumerate() {
+			if level != vol.channel(c as u32) {
+				vol.set_channel(c as u32, level);
+			}
+		}
+		vol.set_master(self.master);
+		vol.set_mute(self.muted);
+	}
+
+	pub fn to_json(&self) -> Json {
+		json!({
+			"master": json::num(self.master),
+			"channels": self.channels.iter().copied().map(json::num).collect::<Vec<_>>(),
+			"muted": self.muted,
+		})
+	}
+
+	pub fn from_json(x: &Json) -> Option<Self> {
+		let level = |x: &Json| {
+			x.as_f64()
+				.filter(|n| n.is_finite() && *n >= 0.0)
+				.map(|n| n as f32)
+		};
+
+		Some(Self {
+			master: level(&x["master"])?,
+			channels: x["channels"]
+				.as_array()?
+				.iter()
+				.map(level)
+				.collect::<Option<_>>()?,
+			muted: x["muted"].as_bool()?,
+		})
+	}
+}
+
+/// Parse a flow written with its `Display` implementation.
+pub fn parse_flow(s: &str) -> Option<Flow> {
+	match s {
+		"output" => Some(Flow::Render),
+		"input" => Some(Flow::Capture),
+		_ => None,
+	}
+}
+
+impl Profile {
+	/// Take a snapshot of the pending levels of `vol`.
+	pub fn new(vol: &Volume, flow: Flow) -> Result<Self> {
+		Ok(Self {
+			id: vol.device().id()?,
+			flow,
+			levels: Levels::pending(vol),
+		})
+	}
+
+	pub fn load(name: &str) -> Result<Self> {
+		let path = path(name)?;
+		let s = match fs::read_to_string(&path) {
+			Ok(s) => s,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				return Err(format!("no such profile: {name}").into());
+			}
+			Err(e) => return Err(format!("failed to read {}: {e}", path.display()).into()),
+		};
+
+		let invalid = || format!("the profile file {} is invalid", path.display());
+		let x = serde_json::from_str::<Json>(&s).map_err(|_| invalid())?;
+		let parse = || {
+			Some(Self {
+				id: x["id"].as_str()?.to_string(),
+				flow: parse_flow(x["flow"].as_str()?)?,
+				levels: Levels::from_json(&x)?,
+			})
+		};
+		parse().ok_or_else(|| invalid().into())
+	}
+
+	pub fn save(&self, name: &str) -> Result<()> {
+		let path = path(name)?;
+		let mut x = self.levels.to_json();
+		x["id"] = json!(self.id);
+		x["flow"] = json!(self.flow.to_string());
+
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)
+				.map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+		}
+		fs::write(&path, format!("{x:#}\n"))
+			.map_err(|e| format!("failed to write {}: {e}", path.display()).into())
+	}
+}
+
+/// Check that a profile name can't point outside the profile directory.
+pub fn validate_name(name: &str) -> Result<(), String> {
+	if name.is_empty()
+		|| name.starts_with('.')
+		|| name.contains(['/', '\\', ':'])
+		|| name.chars().any(char::is_control)
+	{
+		Err(format!("invalid profile name '{name}'"))
+	} else {
+		Ok(())
+	}
+}
+
+fn path(name: &str) -> Result<PathBuf> {
+	validate_name(name)?;
+	Ok(dirs::config_dir()?
+		.join("profiles")
+		.join(format!("{name}.json")))
+}
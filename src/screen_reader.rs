@@ -1,3 +1,4 @@
+#[cfg(windows)]
 use windows::Win32::{
 	Foundation::BOOL,
 	UI::WindowsAndMessaging::{
@@ -7,6 +8,7 @@ use windows::Win32::{
 	},
 };
 
+#[cfg(windows)]
 pub fn is_running() -> bool {
 	unsafe {
 		let mut yes = BOOL(0);
@@ -21,3 +23,8 @@ pub fn is_running() -> bool {
 		ok && yes.as_bool()
 	}
 }
+
+#[cfg(not(windows))]
+pub fn is_running() -> bool {
+	false
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+use crate::device::Device;
+
+/// The state of an audio session.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionState {
+	/// The application is playing or recording audio.
+	Active,
+	/// The application has an open stream that is not playing or recording.
+	Inactive,
+	/// The application has closed its streams.
+	Expired,
+}
+
+impl fmt::Display for SessionState {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Active => "Active",
+			Self::Inactive => "Inactive",
+			Self::Expired => "Expired",
+		})
+	}
+}
+
+/// An application's audio session on a device.
+///
+/// The levels and the mute state of the session are controlled through a [Device], so that adjustments work the same way on sessions as they do on devices.
+pub struct Session {
+	pid: u32,
+	process: String,
+	state: SessionState,
+	control: Device,
+}
+
+impl Session {
+	pub fn new(pid: u32, process: String, state: SessionState, control: Device) -> Self {
+		Self {
+			pid,
+			process,
+			state,
+			control,
+		}
+	}
+
+	/// Get the ID of the process that owns this session.
+	pub fn pid(&self) -> u32 {
+		self.pid
+	}
+
+	/// Get the name of the executable of the process that owns this session.
+	pub fn process(&self) -> &str {
+		&self.process
+	}
+
+	pub fn state(&self) -> SessionState {
+		self.state
+	}
+
+	pub fn control(&self) -> &Device {
+		&self.control
+	}
+
+	pub fn into_control(self) -> Device {
+		self.control
+	}
+
+	/// Whether this session belongs to the given process name or ID.
+	///
+	/// Names are compared case-insensitively and the `.exe` suffix is optional.
+	pub fn matches(&self, app: &str) -> bool {
+		if let Ok(pid) = app.parse::<u32>() {
+			return pid == self.pid;
+		}
+
+		let name = self.process.to_lowercase();
+		let app = app.to_lowercase();
+		name == app || name.strip_suffix(".exe") == Some(&app)
+	}
+}
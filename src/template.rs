@@ -0,0 +1,373 @@
+use std::fmt::Write;
+
+use crate::volume::Volume;
+
+/// A user-defined output format such as `{master}% {dev.name}`.
+///
+/// Placeholders are written as `{name}` or `{name:spec}`; `{{` and `}}` are literal braces.
+/// The spec follows Rust's format syntax: `[[fill]align][0][width][.precision]`, where align is one of `<`, `^` or `>`.
+pub struct Template(Vec<Piece>);
+
+enum Piece {
+	Text(String),
+	Field(Field, Spec),
+}
+
+#[derive(Copy, Clone)]
+enum Chan {
+	N(u32),
+	Left,
+	Right,
+}
+
+#[derive(Copy, Clone)]
+enum Field {
+	Master,
+	MasterDb,
+	Channel(Chan),
+	ChannelDb(Chan),
+	Balance,
+	Muted,
+	Name,
+	Id,
+	State,
+	ChannelCount,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Align {
+	Left,
+	Center,
+	Right,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Spec {
+	fill: Option<char>,
+	align: Option<Align>,
+	zero: bool,
+	width: usize,
+	precision: Option<usize>,
+}
+
+/// The value of a placeholder.
+enum Val {
+	Num(f32),
+	Int(u32),
+	Db(f32),
+	Str(String),
+	/// The value is not available, e.g. the decibels of a dry run or a channel the device doesn't have.
+	Na,
+}
+
+impl Field {
+	fn parse(s: &str) -> Option<Self> {
+		let s = s.to_ascii_lowercase();
+		let chan = |s: &str| match s {
+			"l" | "left" => Some(Chan::Left),
+			"r" | "right" => Some(Chan::Right),
+			_ => s.strip_prefix("ch")?.parse().ok().map(Chan::N),
+		};
+
+		Some(match s.as_str() {
+			"master" => Self::Master,
+			"master_db" => Self::MasterDb,
+			"balance" => Self::Balance,
+			"muted" => Self::Muted,
+			"dev.name" => Self::Name,
+			"dev.id" => Self::Id,
+			"dev.state" => Self::State,
+			"dev.channels" => Self::ChannelCount,
+			_ => match s.strip_suffix("_db") {
+				Some(c) => Self::ChannelDb(chan(c)?),
+				None => Self::Channel(chan(&s)?),
+			},
+		})
+	}
+}
+
+impl Spec {
+	fn parse(s: &str) -> Option<Self> {
+		let mut spec = Self::default();
+		let align = |c| match c {
+			'<' => Some(Align::Left),
+			'^' => Some(Align::Center),
+			'>' => Some(Align::Right),
+			_ => None,
+		};
+
+		let mut chars = s.chars();
+		let mut rest = s;
+		match (chars.next(), chars.next()) {
+			(Some(fill), Some(c)) if align(c).is_some() => {
+				spec.fill = Some(fill);
+				spec.align = align(c);
+				rest = chars.as_str();
+			}
+			(Some(c), _) if align(c).is_some() => {
+				spec.align = align(c);
+				rest = &s[1..];
+			}
+			_ => (),
+		}
+
+		if let Some(s) = rest.strip_prefix('0') {
+			spec.zero = true;
+			rest = s;
+		}
+
+		let (width, precision) = match rest.split_once('.') {
+			Some((w, p)) => (w, Some(p.parse().ok()?)),
+			None => (rest, None),
+		};
+		if !width.is_empty() {
+			spec.width = width.parse().ok()?;
+		}
+		spec.precision = precision;
+
+		Some(spec)
+	}
+
+	fn pad(&self, s: String, numeric: bool) -> String {
+		let len = s.chars().count();
+		if len >= self.width {
+			return s;
+		}
+		let n = self.width - len;
+
+		// Like Rust, the 0 flag pads numbers after the sign and overrides the fill and alignment.
+		if self.zero && numeric {
+			let (sign, digits) = match s.strip_prefix('-') {
+				Some(d) => ("-", d),
+				None => ("", s.as_str()),
+			};
+			return format!("{sign}{}{digits}", "0".repeat(n));
+		}
+
+		let fill = self.fill.unwrap_or(' ').to_string();
+		let align = self
+			.align
+			.unwrap_or(if numeric { Align::Right } else { Align::Left });
+		let (before, after) = match align {
+			Align::Left => (0, n),
+			Align::Center => (n / 2, n - n / 2),
+			Align::Right => (n, 0),
+		};
+		format!("{}{s}{}", fill.repeat(before), fill.repeat(after))
+	}
+}
+
+impl Template {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let mut pieces = Vec::new();
+		let mut text = String::new();
+		let mut rest = s;
+
+		while let Some(i) = rest.find(['{', '}']) {
+			text.push_str(&rest[..i]);
+			let brace = &rest[i..i + 1];
+			rest = &rest[i + 1..];
+
+			if let Some(s) = rest.strip_prefix(brace) {
+				text.push_str(brace);
+				rest = s;
+				continue;
+			} else if brace == "}" {
+				return Err(String::from(
+					"unmatched '}' in the template; use '}}' for a literal brace",
+				));
+			}
+
+			let Some(end) = rest.find('}') else {
+				return Err(String::from(
+					"unmatched '{' in the template; use '{{' for a literal brace",
+				));
+			};
+			let placeholder = &rest[..end];
+			rest = &rest[end + 1..];
+
+			let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+			let field = Field::parse(name.trim())
+				.ok_or_else(|| format!("unknown placeholder '{{{name}}}' in the template"))?;
+			let spec = Spec::parse(spec)
+				.ok_or_else(|| format!("invalid format specifier '{spec}' in the template"))?;
+
+			if !text.is_empty() {
+				pieces.push(Piece::Text(std::mem::take(&mut text)));
+			}
+			pieces.push(Piece::Field(field, spec));
+		}
+
+		text.push_str(rest);
+		if !text.is_empty() {
+			pieces.push(Piece::Text(text));
+		}
+
+		Ok(Self(pieces))
+	}
+
+	/// Fill in the template with the levels of `vol`; the decibels are not available for dry runs since the changes are not applied.
+	///
+	/// Levels without a precision in their spec are rounded to `precision` decimal places.
+	pub fn render(&self, vol: &Volume, dry: bool, precision: usize) -> String {
+		let mut out = String::new();
+		for p in &self.0 {
+			match p {
+				Piece::Text(s) => out.push_str(s),
+				Piece::Field(field, spec) => {
+					let _ = write!(out, "{}", format(value(*field, vol, dry), spec, precision));
+				}
+			}
+		}
+
+		out
+	}
+}
+
+fn value(field: Field, vol: &Volume, dry: bool) -> Val {
+	let index = |c| match c {
+		Chan::N(n) => n,
+		Chan::Left => 0,
+		Chan::Right => 1,
+	};
+	let db = |x: crate::error::Result<f32>| match x {
+		Ok(x) if !dry => Val::Db(x),
+		_ => Val::Na,
+	};
+	let dev = vol.device();
+
+	match field {
+		Field::Master => Val::Num(vol.master() * 100.0),
+		Field::MasterDb => db(vol.master_active_db()),
+		Field::Channel(c) if index(c) < vol.chan_count() => Val::Num(vol.channel(index(c)) * 100.0),
+		Field::ChannelDb(c) if index(c) < vol.chan_count() => db(vol.channel_active_db(index(c))),
+		Field::Channel(_) | Field::ChannelDb(_) => Val::Na,
+		Field::Balance => Val::Num(vol.balance().unwrap_or(0.0) * 100.0),
+		Field::Muted => Val::Str(String::from(if vol.is_muted() { "muted" } else { "" })),
+		Field::Name => Val::Str(dev.name().to_string()),
+		Field::Id => dev.id().map_or(Val::Na, Val::Str),
+		Field::State => Val::Str(dev.state().to_string()),
+		Field::ChannelCount => Val::Int(vol.chan_count()),
+	}
+}
+
+fn format(val: Val, spec: &Spec, precision: usize) -> String {
+	match val {
+		// Levels are rounded like in the text output unless a precision is given.
+		Val::Num(x) => {
+			let s = format!("{:.*}", spec.precision.unwrap_or(precision), x);
+			// Avoid printing "-0".
+			let s = match s.strip_prefix('-') {
+				Some(d) if d.chars().all(|c| c == '0' || c == '.') => d.to_string(),
+				_ => s,
+			};
+			spec.pad(s, true)
+		}
+		Val::Int(n) => spec.pad(n.to_string(), true),
+		Val::Db(x) => {
+			let s = match spec.precision {
+				Some(p) => format!("{x:.p$}"),
+				None => {
+					let s = format!("{x:.2}");
+					let s = s.trim_end_matches('0');
+					s.strip_suffix('.').unwrap_or(s).to_string()
+				}
+			};
+			spec.pad(s, true)
+		}
+		Val::Str(s) => {
+			let s = match spec.precision {
+				// Like Rust, the precision truncates strings.
+				Some(p) => s.chars().take(p).collect(),
+				None => s,
+			};
+			spec.pad(s, false)
+		}
+		Val::Na => spec.pad(String::from("n/a"), false),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		backend::{
+			memory::Memory,
+			Backend,
+		},
+		device::Flow,
+	};
+
+	fn render(template: &str, dry: bool) -> String {
+		let backend = Memory::new("Speakers=50,40").unwrap();
+		let vol = Volume::new(backend.default_device(Flow::Render).unwrap()).unwrap();
+		Template::parse(template).unwrap().render(&vol, dry, 0)
+	}
+
+	#[test]
+	fn placeholders() {
+		assert_eq!(
+			render(
+				"{master}% {dev.name} {l}/{r} {balance} {dev.channels}",
+				false
+			),
+			"50% Speakers 50/40 -20 2"
+		);
+		assert_eq!(
+			render("{master_db} {ch1_db:.1} {ch5}", false),
+			"-6.02 -8.0 n/a"
+		);
+		assert_eq!(render("{MASTER_DB}", true), "n/a");
+		assert_eq!(render("[{muted}]", false), "[]");
+	}
+
+	#[test]
+	fn specs() {
+		assert_eq!(
+			render("{master:>5}|{master:<5}|{master:^6}", false),
+			"   50|50   |  50  "
+		);
+		assert_eq!(render("{master:*^7.1}", false), "*50.0**");
+		assert_eq!(
+			render("{master:05.1} {master_db:06.1}", false),
+			"050.0 -006.0"
+		);
+		assert_eq!(
+			render("{dev.name:.3} {dev.name:-<10}", false),
+			"Spe Speakers--"
+		);
+		assert_eq!(
+			render("{dev.name:5}|{dev.channels:3}", false),
+			"Speakers|  2"
+		);
+	}
+
+	#[test]
+	fn braces() {
+		assert_eq!(render("{{master}} {{{master}}}", false), "{master} {50}");
+		assert_eq!(render("no placeholders", false), "no placeholders");
+	}
+
+	#[test]
+	fn errors() {
+		let err = |s| Template::parse(s).err().unwrap();
+		assert_eq!(
+			err("{volume}"),
+			"unknown placeholder '{volume}' in the template"
+		);
+		assert_eq!(
+			err("{ch_db}"),
+			"unknown placeholder '{ch_db}' in the template"
+		);
+		assert_eq!(
+			err("{master:x5}"),
+			"invalid format specifier 'x5' in the template"
+		);
+		assert_eq!(
+			err("{master:.}"),
+			"invalid format specifier '.' in the template"
+		);
+		assert!(err("{master").starts_with("unmatched '{'"));
+		assert!(err("master}").starts_with("unmatched '}'"));
+	}
+}
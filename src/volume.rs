@@ -1,40 +1,235 @@
-use windows::core::Result as WinResult;
+use std::{
+	f32::consts::FRAC_PI_4,
+	fmt,
+	io::{
+		self,
+		IsTerminal,
+		Write,
+	},
+};
 
 use crate::{
-	device::Device,
-	error::Result,
+	device::{
+		Device,
+		Position,
+	},
+	error::{
+		Error,
+		Result,
+	},
 	screen_reader,
 };
 
+/// How the levels of the left and right channels follow the balance.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PanLaw {
+	/// The quieter channel goes down linearly; e.g. at a balance of -20 the right channel is at 80% of the left.
+	#[default]
+	Linear,
+	/// The channels follow the cosine and the sine of the pan angle, which keeps the acoustic power constant as the balance moves.
+	ConstantPower,
+}
+
+impl PanLaw {
+	/// The gains of the left and right channels at the given balance, scaled so that the louder one is 1.
+	fn gains(self, balance: f32) -> (f32, f32) {
+		let (l, r) = match self {
+			Self::Linear => (1.0 - balance.max(0.0), 1.0 + balance.min(0.0)),
+			Self::ConstantPower => {
+				let angle = (balance + 1.0) * FRAC_PI_4;
+				(angle.cos(), angle.sin())
+			}
+		};
+		let max = l.max(r);
+		(l / max, r / max)
+	}
+
+	/// The inverse of [PanLaw::gains].
+	fn balance(self, l: f32, r: f32) -> f32 {
+		let max = l.max(r);
+		if max == 0.0 {
+			return 0.0;
+		}
+
+		match self {
+			Self::Linear => (r - l) / max,
+			Self::ConstantPower => r.atan2(l) / FRAC_PI_4 - 1.0,
+		}
+	}
+}
+
+/// The maximum level of a device, set with --max or in the configuration file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Limit {
+	/// A scalar between 0 and 1.
+	Scalar(f32),
+	Db(f32),
+}
+
+/// What happens to adjustments that go above the maximum level.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum LimitAction {
+	/// Lower the levels to the maximum and print a warning.
+	#[default]
+	Clamp,
+	/// Fail unless --force is given.
+	Refuse,
+}
+
+impl Limit {
+	/// Parse a percentage such as `80` or `80%`, or a level in decibels such as `-10db`.
+	pub fn parse(s: &str) -> Result<Self, &'static str> {
+		if let Some(n) = s
+			.strip_suffix(['b', 'B'])
+			.and_then(|s| s.strip_suffix(['d', 'D']))
+		{
+			return match n.parse::<f32>() {
+				Ok(n) if n.is_finite() => Ok(Self::Db(n)),
+				_ => Err("the maximum in decibels must be a number followed by 'db', e.g. '-10db'"),
+			};
+		}
+
+		match s.strip_suffix('%').unwrap_or(s).parse::<f32>() {
+			Ok(n) if (0.0..=100.0).contains(&n) => Ok(Self::Scalar(n / 100.0)),
+			_ => Err(
+				"the maximum must be a number from 0 to 100 or a level in decibels such as '-10db'",
+			),
+		}
+	}
+}
+
+impl fmt::Display for Limit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Scalar(x) => write!(f, "{}%", (x * 10000.0).round() / 100.0),
+			Self::Db(db) => write!(f, "{db}db"),
+		}
+	}
+}
+
+/// What [Volume::limit] found above the maximum level.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limited {
+	/// Some channels went above the maximum and are lowered to it.
+	pub lowered: bool,
+	/// Some channels were already above the maximum and went up; they are held at their current level.
+	pub held: bool,
+}
+
+impl Limited {
+	pub fn any(self) -> bool {
+		self.lowered || self.held
+	}
+}
+
+/// When the floor applies.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FloorPolicy {
+	/// Only while a screen reader is running.
+	#[default]
+	ScreenReader,
+	Always,
+}
+
+/// The level that the volume can't be lowered below without --force; muting counts as going below it.
+#[derive(Copy, Clone, Debug)]
+pub struct Floor {
+	/// A scalar between 0 and 1.
+	pub level: f32,
+	pub policy: FloorPolicy,
+	/// Ask for confirmation on a terminal instead of refusing.
+	pub confirm: bool,
+}
+
+impl FloorPolicy {
+	pub fn parse(s: &str) -> Option<Self> {
+		match s.to_lowercase().as_str() {
+			"screen-reader" => Some(Self::ScreenReader),
+			"always" => Some(Self::Always),
+			_ => None,
+		}
+	}
+}
+
+impl Floor {
+	/// Parse the level of a floor, a percentage such as `5` or `5%`.
+	pub fn parse_level(s: &str) -> Result<f32, &'static str> {
+		match s.strip_suffix('%').unwrap_or(s).parse::<f32>() {
+			Ok(n) if (0.0..=100.0).contains(&n) => Ok(n / 100.0),
+			_ => Err("the floor must be a number from 0 to 100"),
+		}
+	}
+}
+
+impl Default for Floor {
+	fn default() -> Self {
+		Self {
+			level: 0.05,
+			policy: FloorPolicy::default(),
+			confirm: false,
+		}
+	}
+}
+
 pub struct Volume {
 	dev: Device,
+	pan_law: PanLaw,
+	positions: Vec<Option<Position>>,
 	master: f32,
 	channels: Vec<f32>,
+	mute: bool,
+	// Levels set in decibels, which are written as such; the scalars of these are estimates on devices that set decibels natively.
+	master_db: Option<f32>,
+	channels_db: Vec<Option<f32>>,
+	// Scalars that channels lowered to a maximum in decibels must not end up above; see [Volume::limit].
+	caps: Vec<Option<f32>>,
+	max: Option<Limit>,
+	floor: Floor,
 	init_master: f32,
 	init_channels: Vec<f32>,
+	init_mute: bool,
 }
 
 impl Volume {
-	pub fn new(dev: Device) -> WinResult<Self> {
+	pub fn new(dev: Device) -> Result<Self> {
 		let master = dev.master_volume()?;
+		let mute = dev.is_muted()?;
 		let n_chan = dev.channels()?;
 		let mut channels = Vec::with_capacity(n_chan as usize);
 		for i in 0..n_chan {
 			channels.push(dev.channel_volume(i)?);
 		}
+		let positions = dev
+			.channel_map()
+			.ok()
+			.filter(|map| map.len() == channels.len())
+			.unwrap_or_else(|| Position::default_layout(n_chan));
 
 		Ok(Self {
 			dev,
+			pan_law: PanLaw::default(),
+			positions,
+			master_db: None,
+			channels_db: vec![None; channels.len()],
+			caps: vec![None; channels.len()],
+			max: None,
+			floor: Floor::default(),
 			init_master: master,
 			init_channels: channels.clone(),
+			init_mute: mute,
 			master,
 			channels,
+			mute,
 		})
 	}
 
+	/// Set the level of a channel; levels above 1 are lowered to 1, or to the initial level of the channel if it is above that.
 	pub fn set_channel(&mut self, c: u32, val: f32) {
-		let val = val.clamp(0.0, 1.0);
+		let val = val.clamp(0.0, self.init_channels[c as usize].max(1.0));
 		self.channels[c as usize] = val;
+		self.channels_db[c as usize] = None;
+		self.caps[c as usize] = None;
+		self.master_db = None;
 		self.master = self
 			.channels
 			.iter()
@@ -43,8 +238,89 @@ impl Volume {
 			.unwrap_or(self.master);
 	}
 
+	pub fn set_floor(&mut self, floor: Floor) {
+		self.floor = floor;
+	}
+
+	/// Set the maximum level of the channels, which is enforced by [Volume::limit].
+	pub fn set_max(&mut self, limit: Limit) {
+		self.max = Some(limit);
+	}
+
+	/// Check the pending levels against the maximum level, lowering them to it if `clamp` is set.
+	///
+	/// Only channels that go up are limited, so a device that is already above the maximum doesn't get quieter by itself.
+	/// A maximum in decibels is compared with the levels in decibels, since backends map decibels to scalars differently.
+	/// The decibels of a channel set as a scalar are only an estimate on some backends (e.g. WASAPI), so such a channel is lowered to the maximum in decibels
+	/// and then, once written, put back to its pending scalar if the device put it above that.
+	pub fn limit(&mut self, clamp: bool) -> Result<Limited> {
+		let mut limited = Limited::default();
+		let Some(max) = self.max else {
+			return Ok(limited);
+		};
+
+		for c in 0..self.channels.len() as u32 {
+			let i = c as usize;
+			let init = self.init_channels[i];
+			// The scalar of a level set in decibels is an estimate, so only scalars that went down are known to stay below the maximum.
+			if self.channels_db[i].is_none() && self.master_db.is_none() && self.channels[i] <= init
+			{
+				continue;
+			}
+
+			let (over, held) = match max {
+				Limit::Scalar(max) => (self.channels[i] > max.max(init), init > max),
+				Limit::Db(max) => {
+					// Fail on backends without decibel support rather than going by a guess.
+					self.dev.db_range()?;
+					let init = self
+						.dev
+						.channel_db(c)
+						.unwrap_or_else(|_| self.dev.scalar_to_db(init));
+					(self.channel_db(c)? > max.max(init), init > max)
+				}
+			};
+			if !over {
+				continue;
+			}
+
+			if held {
+				limited.held = true;
+			} else {
+				limited.lowered = true;
+			}
+			if clamp {
+				match max {
+					_ if held => self.set_channel(c, init),
+					Limit::Scalar(max) => self.set_channel(c, max),
+					Limit::Db(max) => {
+						let estimated = self.channels_db[i].is_none() && self.master_db.is_none();
+						let pending = self.channels[i];
+						self.set_channel_db(c, max)?;
+						if estimated {
+							self.caps[i] = Some(pending);
+						}
+					}
+				}
+			}
+		}
+
+		Ok(limited)
+	}
+
+	/// Set every channel in `to` to the level the channel at the same index in `from` had before any adjustment.
+	pub fn mirror(&mut self, to: &[u32], from: &[u32]) {
+		for (&t, &f) in to.iter().zip(from) {
+			self.set_channel(t, self.init_channels[f as usize]);
+		}
+	}
+
+	/// Set the master level, scaling the channels; like [Volume::set_channel], levels above 1 are only kept up to the initial master level.
 	pub fn set_master(&mut self, val: f32) {
-		let val = val.clamp(0.0, 1.0);
+		let val = val.clamp(0.0, self.init_master.max(1.0));
+		self.master_db = None;
+		self.channels_db.iter_mut().for_each(|x| *x = None);
+		self.caps.iter_mut().for_each(|x| *x = None);
 		if val == 0.0 {
 			self.master = 0.0;
 			self.channels.iter_mut().for_each(|n| *n = 0.0);
@@ -63,6 +339,101 @@ impl Volume {
 		self.master = val;
 	}
 
+	/// Set the master level in decibels, clamped to the range of the device.
+	pub fn set_master_db(&mut self, db: f32) -> Result<()> {
+		let (min, max) = self.dev.db_range()?;
+		let db = db.clamp(min, max);
+		self.set_master(self.dev.db_to_scalar(db));
+		self.master_db = Some(db);
+		Ok(())
+	}
+
+	/// Set the level of a channel in decibels, clamped to the range of the device.
+	pub fn set_channel_db(&mut self, c: u32, db: f32) -> Result<()> {
+		let (min, max) = self.dev.db_range()?;
+		let db = db.clamp(min, max);
+		// Writing the channel after the master keeps a master level set in decibels valid.
+		let master_db = self.master_db;
+		self.set_channel(c, self.dev.db_to_scalar(db));
+		self.channels_db[c as usize] = Some(db);
+		self.master_db = master_db;
+		Ok(())
+	}
+
+	/// Get the pending master level in decibels.
+	pub fn master_db(&self) -> Result<f32> {
+		if let Some(db) = self.master_db {
+			Ok(db)
+		} else if self.channels == self.init_channels {
+			self.dev.master_db()
+		} else {
+			Ok(self.dev.scalar_to_db(self.master))
+		}
+	}
+
+	/// Get the pending level of a channel in decibels.
+	pub fn channel_db(&self, c: u32) -> Result<f32> {
+		let i = c as usize;
+		if let Some(db) = self.channels_db[i] {
+			Ok(db)
+		} else if self.channels[i] == self.init_channels[i] {
+			self.dev.channel_db(c)
+		} else {
+			Ok(self.dev.scalar_to_db(self.channels[i]))
+		}
+	}
+
+	/// Whether any pending level is set in decibels.
+	pub fn has_db(&self) -> bool {
+		self.master_db.is_some() || self.channels_db.iter().any(Option::is_some)
+	}
+
+	pub fn set_pan_law(&mut self, pan_law: PanLaw) {
+		self.pan_law = pan_law;
+	}
+
+	/// Get the balance between the left and right channels from -1 (left) to 1 (right), or None if the device has fewer than 2 channels.
+	pub fn balance(&self) -> Option<f32> {
+		match self.channels[..] {
+			[l, r, ..] => Some(self.pan_law.balance(l, r)),
+			_ => None,
+		}
+	}
+
+	/// Redistribute the levels of the left and right channels according to the pan law.
+	///
+	/// The louder of the two keeps its level, so the master level doesn't change.
+	pub fn set_balance(&mut self, balance: f32) -> Result<()> {
+		let [l, r, ..] = self.channels[..] else {
+			return Err(
+				"the balance can only be adjusted on devices with at least 2 channels".into(),
+			);
+		};
+
+		let level = l.max(r);
+		let (l, r) = self.pan_law.gains(balance.clamp(-1.0, 1.0));
+		self.channels[0] = level * l;
+		self.channels[1] = level * r;
+		self.channels_db[0] = None;
+		self.channels_db[1] = None;
+		self.caps[0] = None;
+		self.caps[1] = None;
+		self.master_db = None;
+		Ok(())
+	}
+
+	pub fn set_mute(&mut self, mute: bool) {
+		self.mute = mute;
+	}
+
+	pub fn device(&self) -> &Device {
+		&self.dev
+	}
+
+	pub fn into_device(self) -> Device {
+		self.dev
+	}
+
 	pub fn chan_count(&self) -> u32 {
 		self.channels.len() as u32
 	}
@@ -71,6 +442,10 @@ impl Volume {
 		self.master
 	}
 
+	pub fn is_muted(&self) -> bool {
+		self.mute
+	}
+
 	pub fn channel(&self, c: u32) -> f32 {
 		self.channels[c as usize]
 	}
@@ -79,36 +454,143 @@ impl Volume {
 		&self.channels
 	}
 
-	pub fn master_active_db(&self) -> WinResult<f32> {
+	/// Get the speaker position of a channel, if known.
+	pub fn position(&self, c: u32) -> Option<Position> {
+		self.positions.get(c as usize).copied().flatten()
+	}
+
+	/// Find the channel at the given speaker position.
+	pub fn find_position(&self, pos: Position) -> Option<u32> {
+		self.positions
+			.iter()
+			.position(|&p| p == Some(pos))
+			.map(|i| i as u32)
+	}
+
+	pub fn master_active_db(&self) -> Result<f32> {
 		self.dev.master_db()
 	}
 
-	pub fn channel_active_db(&self, channel: u32) -> WinResult<f32> {
+	pub fn channel_active_db(&self, channel: u32) -> Result<f32> {
 		self.dev.channel_db(channel)
 	}
 
-	pub fn commit(&self, force: bool) -> Result<()> {
-		// Try not to set the volume below 5% for people that use a screen reader.
-		if !force && self.master < self.init_master && self.master < 0.05 {
-			let old_max = self
-				.init_channels
-				.iter()
-				.copied()
-				.max_by(f32::total_cmp)
-				.unwrap_or(1.0);
-			let new_max = self
-				.channels
-				.iter()
-				.copied()
-				.max_by(f32::total_cmp)
-				.unwrap_or(1.0);
-			if new_max < old_max && new_max < 0.05 && screen_reader::is_running() {
-				return Err("a screen reader is detected; refusing to set the volume below 5%\nhint: use --force to override this behaviour".into());
+	pub fn init_master(&self) -> f32 {
+		self.init_master
+	}
+
+	pub fn init_channels(&self) -> &[f32] {
+		&self.init_channels
+	}
+
+	pub fn init_muted(&self) -> bool {
+		self.init_mute
+	}
+
+	/// Check the pending changes against the screen reader safeguards.
+	pub fn check(&self, force: bool) -> Result<()> {
+		if force {
+			return Ok(());
+		}
+
+		let floor = self.floor.level;
+		let max = |levels: &[f32]| levels.iter().copied().max_by(f32::total_cmp).unwrap_or(1.0);
+		let lowered = self.master < self.init_master
+			&& self.master < floor
+			&& max(&self.channels) < max(&self.init_channels)
+			&& max(&self.channels) < floor;
+		// Muting is as good as going quiet, unless there is no floor at all.
+		let muted = floor > 0.0 && self.mute && !self.init_mute;
+		if !lowered && !muted {
+			return Ok(());
+		}
+
+		// Try not to set the volume below the floor for people that use a screen reader.
+		let reason = match self.floor.policy {
+			FloorPolicy::Always => "",
+			FloorPolicy::ScreenReader if screen_reader::is_running() => {
+				"a screen reader is detected; "
 			}
+			FloorPolicy::ScreenReader => return Ok(()),
+		};
+		let action = if lowered {
+			format!(
+				"set the volume below {}%",
+				(floor * 10000.0).round() / 100.0
+			)
+		} else {
+			String::from("mute the device")
+		};
+
+		if self.floor.confirm && confirm(&format!("{reason}{action} anyway?")) {
+			return Ok(());
 		}
+		Err(Error::Refused(
+			format!("{reason}refusing to {action}\nhint: use --force to override this behaviour")
+				.into(),
+		))
+	}
+
+	/// Write the given levels to the device without changing the pending ones; e.g. an intermediate step of a fade.
+	pub fn write_channels(&self, levels: &[f32]) -> Result<()> {
+		self.dev.set_channel_volumes(levels)
+	}
+
+	pub fn write_mute(&self, mute: bool) -> Result<()> {
+		self.dev.set_mute(mute)
+	}
+
+	/// Write a level in decibels to the device without changing the pending ones.
+	pub fn write_channel_db(&self, c: u32, db: f32) -> Result<()> {
+		self.dev.set_channel_db(c, db)
+	}
+
+	/// Write a level to the device without changing the pending ones.
+	pub fn write_channel(&self, c: u32, level: f32) -> Result<()> {
+		self.dev.set_channel_volume(c, level)
+	}
+
+	/// The pending level of a channel in decibels if it is written in decibels, either by itself or through the master level.
+	///
+	/// Only valid before anything is written, since a master level in decibels is relative to the levels of the device.
+	pub fn target_db(&self, c: u32) -> Result<Option<f32>> {
+		if let Some(db) = self.channels_db[c as usize] {
+			return Ok(Some(db));
+		}
+		match self.master_db {
+			// A master level in decibels moves every channel by the same number of decibels.
+			Some(db) => Ok(Some(self.dev.channel_db(c)? + db - self.dev.master_db()?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Write the pending levels that are set in decibels.
+	pub fn write_db(&self) -> Result<()> {
+		if let Some(db) = self.master_db {
+			self.dev.set_master_db(db)?;
+		}
+		for (i, db) in self.channels_db.iter().enumerate() {
+			if let Some(db) = *db {
+				self.dev.set_channel_db(i as u32, db)?;
+			}
+		}
+
+		for (i, cap) in self.caps.iter().enumerate() {
+			if let Some(cap) = *cap {
+				if self.dev.channel_volume(i as u32)? > cap {
+					self.dev.set_channel_volume(i as u32, cap)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	pub fn commit(&self, force: bool) -> Result<()> {
+		self.check(force)?;
 
 		let master_changed = self.master != self.init_master;
-		if master_changed {
+		if self.master_db.is_none() && master_changed {
 			self.dev.set_master_volume(self.master)?;
 		}
 
@@ -118,11 +600,298 @@ impl Volume {
 			.zip(self.channels.iter())
 			.enumerate()
 		{
-			if master_changed || old != new {
+			// A master level set in decibels is written with write_db, which scales the channels by itself.
+			if self.channels_db[i].is_none()
+				&& self.master_db.is_none()
+				&& (master_changed || old != new)
+			{
 				self.dev.set_channel_volume(i as u32, new)?;
 			}
 		}
+		self.write_db()?;
+
+		if self.mute != self.init_mute {
+			self.dev.set_mute(self.mute)?;
+		}
 
 		Ok(())
 	}
 }
+
+/// Ask a yes or no question on the terminal; the answer is no if stdin is not a terminal.
+fn confirm(question: &str) -> bool {
+	if !io::stdin().is_terminal() {
+		return false;
+	}
+
+	eprint!("{question} [y/N] ");
+	let _ = io::stderr().flush();
+	let mut answer = String::new();
+	io::stdin().read_line(&mut answer).is_ok()
+		&& matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+
+	use super::*;
+	use crate::{
+		backend::{
+			memory::Memory,
+			Backend,
+			Endpoint,
+		},
+		device::{
+			DeviceState,
+			Flow,
+		},
+	};
+
+	/// A device whose levels are steeper in decibels than the default estimate, like the audio taper of WASAPI.
+	///
+	/// Without `db`, it has no decibel levels at all.
+	struct Tapered {
+		levels: RefCell<Vec<f32>>,
+		db: bool,
+	}
+
+	impl Tapered {
+		fn device(levels: &[f32], db: bool) -> Device {
+			Device::from(Self {
+				levels: RefCell::new(levels.to_vec()),
+				db,
+			})
+		}
+	}
+
+	impl Endpoint for Tapered {
+		fn name(&self) -> &str {
+			"Tapered"
+		}
+
+		fn id(&self) -> Result<String> {
+			Ok(String::from("tapered"))
+		}
+
+		fn state(&self) -> DeviceState {
+			DeviceState::ACTIVE
+		}
+
+		fn channels(&self) -> Result<u32> {
+			Ok(self.levels.borrow().len() as u32)
+		}
+
+		fn master_volume(&self) -> Result<f32> {
+			Ok(self.levels.borrow().iter().copied().fold(0.0, f32::max))
+		}
+
+		fn master_db(&self) -> Result<f32> {
+			self.master_volume().map(|s| 40.0 * s.log10())
+		}
+
+		fn set_master_volume(&self, _volume: f32) -> Result<()> {
+			unimplemented!()
+		}
+
+		fn is_muted(&self) -> Result<bool> {
+			Ok(false)
+		}
+
+		fn set_mute(&self, _mute: bool) -> Result<()> {
+			unimplemented!()
+		}
+
+		fn channel_volume(&self, channel: u32) -> Result<f32> {
+			Ok(self.levels.borrow()[channel as usize])
+		}
+
+		fn channel_db(&self, channel: u32) -> Result<f32> {
+			self.db_range()?;
+			self.channel_volume(channel).map(|s| 40.0 * s.log10())
+		}
+
+		fn set_channel_volume(&self, channel: u32, volume: f32) -> Result<()> {
+			self.levels.borrow_mut()[channel as usize] = volume;
+			Ok(())
+		}
+
+		fn db_range(&self) -> Result<(f32, f32)> {
+			match self.db {
+				true => Ok((-80.0, 0.0)),
+				false => Err("no decibels".into()),
+			}
+		}
+
+		fn set_channel_db(&self, channel: u32, db: f32) -> Result<()> {
+			self.set_channel_volume(channel, 10f32.powf(db / 40.0))
+		}
+	}
+
+	/// The default output device of the memory backend, with a floor of 5% that always applies.
+	fn volume(backend: &Memory) -> Volume {
+		let mut vol = Volume::new(backend.default_device(Flow::Render).unwrap()).unwrap();
+		vol.set_floor(Floor {
+			policy: FloorPolicy::Always,
+			..Floor::default()
+		});
+		vol
+	}
+
+	#[test]
+	fn commit() {
+		let backend = Memory::new("Speakers=50,40").unwrap();
+		let mut vol = volume(&backend);
+		vol.set_channel(1, 0.3);
+		vol.set_mute(true);
+		vol.commit(false).unwrap_err();
+		vol.set_mute(false);
+		vol.commit(false).unwrap();
+
+		let vol = volume(&backend);
+		assert_eq!(vol.channels(), [0.5, 0.3]);
+		assert_eq!(vol.master(), 0.5);
+		assert!(!vol.is_muted());
+	}
+
+	#[test]
+	fn pan_laws() {
+		let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+		for law in [PanLaw::Linear, PanLaw::ConstantPower] {
+			assert_eq!(law.gains(0.0), (1.0, 1.0));
+			assert!(matches!(law.gains(-1.0), (l, r) if l == 1.0 && close(r, 0.0)));
+			assert!(matches!(law.gains(1.0), (l, r) if close(l, 0.0) && r == 1.0));
+
+			for b in [-1.0, -0.6, -0.2, 0.0, 0.3, 0.75, 1.0] {
+				let (l, r) = law.gains(b);
+				assert!(close(law.balance(l, r), b), "{law:?} at {b}");
+				assert!(close(law.balance(l * 0.4, r * 0.4), b), "{law:?} at {b}");
+			}
+		}
+
+		assert_eq!(PanLaw::Linear.gains(-0.2), (1.0, 0.8));
+		let (l, r) = PanLaw::ConstantPower.gains(0.5);
+		assert!(close(l, 2f32.sqrt() - 1.0) && r == 1.0);
+		assert_eq!(PanLaw::Linear.balance(0.0, 0.0), 0.0);
+	}
+
+	#[test]
+	fn balance() {
+		for law in [PanLaw::Linear, PanLaw::ConstantPower] {
+			let backend = Memory::new("Speakers=50,40").unwrap();
+			let mut vol = volume(&backend);
+			vol.set_pan_law(law);
+
+			vol.set_balance(0.0).unwrap();
+			assert_eq!(vol.channels(), [0.5, 0.5]);
+			vol.set_balance(1.0).unwrap();
+			assert_eq!(vol.channels()[1], 0.5);
+			assert!(vol.channels()[0] < 1e-6);
+			assert!((vol.balance().unwrap() - 1.0).abs() < 1e-6);
+			vol.set_balance(-1.0).unwrap();
+			assert_eq!(vol.channels()[0], 0.5);
+			assert!(vol.channels()[1] < 1e-6);
+			vol.set_balance(-0.4).unwrap();
+			assert!((vol.balance().unwrap() + 0.4).abs() < 1e-6);
+			assert_eq!(vol.master(), 0.5);
+		}
+
+		let backend = Memory::new("Mono=50").unwrap();
+		assert!(volume(&backend).set_balance(0.5).is_err());
+	}
+
+	#[test]
+	fn floor() {
+		let backend = Memory::new("Speakers=50,40").unwrap();
+		let mut vol = volume(&backend);
+		vol.set_master(0.04);
+		assert!(matches!(vol.check(false), Err(Error::Refused(_))));
+		assert!(matches!(vol.commit(false), Err(Error::Refused(_))));
+		assert_eq!(volume(&backend).master(), 0.5);
+
+		vol.check(true).unwrap();
+		vol.set_master(0.05);
+		vol.check(false).unwrap();
+	}
+
+	#[test]
+	fn floor_below_initial_level() {
+		// A device that is already below the floor may still go up, or down with its master level.
+		let backend = Memory::new("Speakers=3,3").unwrap();
+		let mut vol = volume(&backend);
+		vol.set_master(0.04);
+		vol.check(false).unwrap();
+		vol.set_master(0.02);
+		assert!(matches!(vol.check(false), Err(Error::Refused(_))));
+	}
+
+	#[test]
+	fn mute_is_quiet() {
+		let backend = Memory::new("Speakers=50,40").unwrap();
+		let mut vol = volume(&backend);
+		vol.set_mute(true);
+		assert!(matches!(vol.check(false), Err(Error::Refused(_))));
+
+		vol.set_floor(Floor {
+			level: 0.0,
+			policy: FloorPolicy::Always,
+			confirm: false,
+		});
+		vol.check(false).unwrap();
+
+		// Unmuting and staying muted are never refused.
+		let backend = Memory::new("Speakers:muted=50,40").unwrap();
+		let mut vol = volume(&backend);
+		vol.check(false).unwrap();
+		vol.set_mute(false);
+		vol.check(false).unwrap();
+	}
+
+	#[test]
+	fn max() {
+		let backend = Memory::new("Speakers=50,40").unwrap();
+		let mut vol = volume(&backend);
+		vol.set_max(Limit::Scalar(0.45));
+		vol.set_master(1.0);
+		let limited = vol.limit(true).unwrap();
+		assert!(limited.lowered && limited.held);
+		assert_eq!(vol.channels(), [0.5, 0.45]);
+	}
+
+	#[test]
+	fn max_db_estimated() {
+		// 0.2 is -28dB on this device but -14dB by the estimate, so it is below a maximum of -20dB after all.
+		let dev = Tapered::device(&[0.1, 0.5], true);
+		let mut vol = Volume::new(dev).unwrap();
+		vol.set_max(Limit::Db(-20.0));
+		vol.set_channel(0, 0.2);
+		vol.set_channel(1, 0.6);
+		let limited = vol.limit(true).unwrap();
+		assert!(limited.lowered && limited.held);
+		vol.commit(true).unwrap();
+
+		let dev = vol.into_device();
+		assert_eq!(dev.channel_volume(0).unwrap(), 0.2);
+		assert_eq!(dev.channel_volume(1).unwrap(), 0.5);
+
+		// A level that is above the maximum on the device is lowered to it.
+		let mut vol = Volume::new(dev).unwrap();
+		vol.set_max(Limit::Db(-20.0));
+		vol.set_channel(0, 0.4);
+		vol.limit(true).unwrap();
+		vol.commit(true).unwrap();
+		assert_eq!(vol.device().channel_db(0).unwrap().round(), -20.0);
+	}
+
+	#[test]
+	fn max_db_unsupported() {
+		// Leaving the levels alone or lowering them needs no decibels.
+		let mut vol = Volume::new(Tapered::device(&[0.5, 0.5], false)).unwrap();
+		vol.set_max(Limit::Db(-20.0));
+		assert!(!vol.limit(false).unwrap().any());
+		vol.set_channel(0, 0.1);
+		assert!(!vol.limit(false).unwrap().any());
+		vol.set_channel(1, 0.6);
+		vol.limit(false).unwrap_err();
+	}
+}